@@ -0,0 +1,151 @@
+//! `#[derive(Encode, Decode)]` for `iron_oxide::io`.
+//!
+//! Struct fields are written in declaration order. Enums write the variant's
+//! index as a varint, then its fields. Every derived decode is a level of
+//! `ByteReader::nested`, which bounds the recursion of recursive types.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Generics, Index};
+
+#[proc_macro_derive(Encode)]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, writes) = destructure(&data.fields);
+            quote! {
+                let Self #pattern = self;
+                #writes
+            }
+        }
+        // A reference to an empty enum isn't matched exhaustively by `{}`.
+        Data::Enum(data) if data.variants.is_empty() => quote!(match *self {}),
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let ident = &variant.ident;
+                let index = index as u64;
+                let (pattern, writes) = destructure(&variant.fields);
+                quote! {
+                    Self::#ident #pattern => {
+                        writer.write_varint(#index);
+                        #writes
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return unsupported(name, "Encode"),
+    };
+    let generics = bounded(input.generics.clone(), quote!(::iron_oxide::io::Encode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::iron_oxide::io::Encode for #name #ty_generics #where_clause {
+            fn encode(&self, writer: &mut ::iron_oxide::io::ByteWriter) {
+                #body
+            }
+        }
+    }
+    .into()
+}
+
+#[proc_macro_derive(Decode)]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = construct(&data.fields);
+            quote!(::std::result::Result::Ok(Self #fields))
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let ident = &variant.ident;
+                let index = index as u64;
+                let fields = construct(&variant.fields);
+                quote!(#index => ::std::result::Result::Ok(Self::#ident #fields),)
+            });
+            let expected = format!("a {name} variant");
+            quote! {
+                let offset = reader.offset();
+                match reader.try_read_varint()? {
+                    #(#arms)*
+                    _ => ::std::result::Result::Err(::iron_oxide::io::ReadError::InvalidValue { offset, expected: #expected }),
+                }
+            }
+        }
+        Data::Union(_) => return unsupported(name, "Decode"),
+    };
+    let generics = bounded(input.generics.clone(), quote!(::iron_oxide::io::Decode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::iron_oxide::io::Decode for #name #ty_generics #where_clause {
+            fn decode<__T: ::std::convert::AsRef<[u8]>>(
+                reader: &mut ::iron_oxide::io::ByteReader<__T>,
+            ) -> ::std::result::Result<Self, ::iron_oxide::io::ReadError> {
+                reader.nested(|reader| {
+                    #body
+                })
+            }
+        }
+    }
+    .into()
+}
+
+/// A pattern binding every field to `__field{n}`, and the writes of those bindings.
+fn destructure(fields: &Fields) -> (TokenStream2, TokenStream2) {
+    let bindings: Vec<_> = (0..fields.len()).map(|i| format_ident!("__field{i}")).collect();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!({ #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!((#(#bindings),*)),
+        Fields::Unit => quote!(),
+    };
+    let writes = quote!(#(::iron_oxide::io::Encode::encode(#bindings, writer);)*);
+    (pattern, writes)
+}
+
+/// The field list of a constructor that decodes every field in order.
+fn construct(fields: &Fields) -> TokenStream2 {
+    let decode = quote!(::iron_oxide::io::Decode::decode(reader)?);
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!({ #(#names: #decode),* })
+        }
+        Fields::Unnamed(unnamed) => {
+            let decodes = unnamed.unnamed.iter().enumerate().map(|(i, _)| {
+                let index = Index::from(i);
+                quote!(#index: #decode)
+            });
+            quote!({ #(#decodes),* })
+        }
+        Fields::Unit => quote!(),
+    }
+}
+
+/// Requires `bound` of every type parameter.
+fn bounded(mut generics: Generics, bound: TokenStream2) -> Generics {
+    let params: Vec<_> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause.predicates.push(parse_quote!(#param: #bound));
+    }
+    generics
+}
+
+fn unsupported(name: &syn::Ident, derive: &str) -> TokenStream {
+    syn::Error::new(name.span(), format!("{derive} can't be derived for the union {name}"))
+        .to_compile_error()
+        .into()
+}
@@ -0,0 +1,297 @@
+use super::{ByteReader, ByteWriter, ReadError};
+
+/// Which end of a byte bits are taken from first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The high bit comes first and the first bit read is the value's highest,
+    /// as in most packed-flag formats.
+    MsbFirst,
+    /// The low bit comes first and the first bit read is the value's lowest,
+    /// as in DEFLATE.
+    LsbFirst,
+}
+
+/// The low `bits` bits set, for `bits <= 8`.
+const fn mask(bits: usize) -> u64 {
+    (1 << bits) - 1
+}
+
+/// Reads values of up to 64 bits that need not start or end on a byte boundary.
+///
+/// Like [`ByteReader`], the `read_*` methods panic on short input and the
+/// `try_read_*` ones return a [`ReadError`] with byte offsets.
+pub struct BitReader<T> {
+    inner: T,
+    /// Position in bits.
+    pos: usize,
+    /// Byte offset of `inner` in the outermost reader, for [`ReadError`]s.
+    base: usize,
+    order: BitOrder,
+}
+
+impl<T> BitReader<T> {
+    pub const fn new(inner: T, order: BitOrder) -> Self {
+        BitReader { inner, pos: 0, base: 0, order }
+    }
+
+    /// Continues at the byte position of `reader`. Errors keep counting
+    /// offsets from where `reader` does, also for a sub-reader.
+    pub fn from_byte_reader(reader: ByteReader<T>, order: BitOrder) -> Self {
+        let (pos, base) = (reader.position() * 8, reader.offset() - reader.position());
+        BitReader { inner: reader.into_inner(), pos, base, order }
+    }
+
+    pub const fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    pub const fn bit_position(&self) -> usize {
+        self.pos
+    }
+
+    pub const fn is_aligned(&self) -> bool {
+        self.pos.is_multiple_of(8)
+    }
+
+    /// Skips the rest of the current byte.
+    pub const fn align(&mut self) {
+        self.pos = self.pos.next_multiple_of(8);
+    }
+
+    /// Continues byte-wise at the next whole byte, skipping the rest of the
+    /// current one.
+    pub fn into_byte_reader(self) -> ByteReader<T> {
+        ByteReader::with_base(self.inner, self.pos.div_ceil(8), self.base)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> BitReader<T>
+where T: AsRef<[u8]> {
+
+    pub fn remaining_bits(&self) -> usize {
+        (self.inner.as_ref().len() * 8).saturating_sub(self.pos)
+    }
+
+    /// Reads `count` bits, at most 64.
+    pub fn read_bits(&mut self, count: usize) -> u64 {
+        assert!(count <= 64, "can't read {count} bits at once, at most 64");
+        self.try_read_bits(count).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like [`read_bits`](Self::read_bits), but a `count` above 64 is a
+    /// [`ReadError::InvalidValue`] instead of a panic.
+    pub fn try_read_bits(&mut self, count: usize) -> Result<u64, ReadError> {
+        let value = self.peek_bits(count)?;
+        self.pos += count;
+        Ok(value)
+    }
+
+    /// The next `count` bits, without advancing.
+    pub fn peek_bits(&self, count: usize) -> Result<u64, ReadError> {
+        if count > 64 {
+            return Err(ReadError::InvalidValue { offset: self.base + self.pos / 8, expected: "a bit count of at most 64" });
+        }
+        if count > self.remaining_bits() {
+            let (offset, end) = (self.pos / 8, (self.pos + count).div_ceil(8));
+            let available = self.inner.as_ref().len().saturating_sub(offset);
+            return Err(ReadError::UnexpectedEof { offset: self.base + offset, needed: end - offset, available });
+        }
+
+        let bytes = self.inner.as_ref();
+        let (mut value, mut read) = (0, 0);
+        while read < count {
+            let pos = self.pos + read;
+            let (byte, used) = (bytes[pos / 8] as u64, pos % 8);
+            let take = (8 - used).min(count - read);
+            match self.order {
+                BitOrder::MsbFirst => value = value << take | (byte >> (8 - used - take)) & mask(take),
+                BitOrder::LsbFirst => value |= (byte >> used & mask(take)) << read,
+            }
+            read += take;
+        }
+        Ok(value)
+    }
+
+    pub fn read_bit(&mut self) -> bool {
+        self.read_bits(1) != 0
+    }
+
+    pub fn try_read_bit(&mut self) -> Result<bool, ReadError> {
+        self.try_read_bits(1).map(|bit| bit != 0)
+    }
+
+    pub fn is_read_finished(&self) -> bool {
+        self.remaining_bits() == 0
+    }
+}
+
+/// Writes values of up to 64 bits, packed without padding.
+pub struct BitWriter {
+    buf: Vec<u8>,
+    /// Bits used in the last byte of `buf`, 0 if it is full.
+    used: usize,
+    order: BitOrder,
+}
+
+impl BitWriter {
+    pub const fn new(order: BitOrder) -> Self {
+        BitWriter { buf: Vec::new(), used: 0, order }
+    }
+
+    pub const fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    pub const fn bit_len(&self) -> usize {
+        match self.used {
+            0 => self.buf.len() * 8,
+            used => (self.buf.len() - 1) * 8 + used,
+        }
+    }
+
+    pub const fn is_aligned(&self) -> bool {
+        self.used == 0
+    }
+
+    /// Pads the current byte with zero bits.
+    pub const fn align(&mut self) {
+        self.used = 0;
+    }
+
+    /// Writes the low `count` bits of `value`, at most 64.
+    pub fn write_bits(&mut self, value: u64, count: usize) {
+        assert!(count <= 64, "can't write {count} bits at once, at most 64");
+        let mut written = 0;
+        while written < count {
+            if self.used == 0 {
+                self.buf.push(0);
+            }
+            let take = (8 - self.used).min(count - written);
+            let chunk = match self.order {
+                BitOrder::MsbFirst => (value >> (count - written - take) & mask(take)) << (8 - self.used - take),
+                BitOrder::LsbFirst => (value >> written & mask(take)) << self.used,
+            };
+            *self.buf.last_mut().unwrap() |= chunk as u8;
+            self.used = (self.used + take) % 8;
+            written += take;
+        }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(bit as u64, 1);
+    }
+
+    /// The written bytes, the last one padded with zero bits.
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Continues byte-wise after padding the current byte.
+    pub fn into_byte_writer(self) -> ByteWriter {
+        ByteWriter::from(self.buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_bit_orders_match_known_layouts() {
+        let mut writer = BitWriter::new(BitOrder::MsbFirst);
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0b11110, 5);
+        writer.write_bits(0xABC, 12);
+        assert_eq!(writer.bit_len(), 20);
+        assert_eq!(writer.finish(), [0b1011_1110, 0xAB, 0xC0]);
+
+        // A DEFLATE block header: BFINAL = 1, then BTYPE = 01.
+        let mut writer = BitWriter::new(BitOrder::LsbFirst);
+        writer.write_bit(true);
+        writer.write_bits(0b01, 2);
+        writer.write_bits(0x1FF, 9);
+        assert_eq!(writer.finish(), [0b1111_1011, 0b0000_1111]);
+
+        let mut reader = BitReader::new([0b1111_1011, 0b0000_1111], BitOrder::LsbFirst);
+        assert_eq!((reader.read_bit(), reader.read_bits(2)), (true, 0b01));
+        assert_eq!(reader.peek_bits(9), Ok(0x1FF));
+        let mut reader = BitReader::new([0b1011_1110, 0xAB, 0xC0], BitOrder::MsbFirst);
+        assert_eq!((reader.read_bits(3), reader.read_bits(5), reader.read_bits(12)), (0b101, 0b11110, 0xABC));
+    }
+
+    fn mask_64(bits: usize) -> u64 {
+        u64::MAX.checked_shr(64 - bits as u32).unwrap_or(0)
+    }
+
+    #[test]
+    fn random_fields_round_trip() {
+        for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let fields: Vec<(u64, usize)> = (0..500)
+                .map(|i| {
+                    let count = crate::rand::hash(i) as usize % 65;
+                    let value = (crate::rand::hash(i + 1000) as u64) << 32 | crate::rand::hash(i + 2000) as u64;
+                    (value & mask_64(count), count)
+                })
+                .collect();
+            let mut writer = BitWriter::new(order);
+            for &(value, count) in &fields {
+                writer.write_bits(value, count);
+            }
+            let bit_len = writer.bit_len();
+            let mut reader = BitReader::new(writer.finish(), order);
+            for &(value, count) in &fields {
+                assert_eq!(reader.try_read_bits(count), Ok(value));
+            }
+            assert_eq!(reader.bit_position(), bit_len);
+            assert!(reader.remaining_bits() < 8);
+        }
+    }
+
+    #[test]
+    fn switches_between_bits_and_bytes() {
+        let mut writer = BitWriter::new(BitOrder::MsbFirst);
+        writer.write_bits(0b11, 2);
+        let mut bytes = writer.into_byte_writer();
+        bytes.write_be_u16(0x1234);
+        let data = bytes.finish();
+        assert_eq!(data, [0b1100_0000, 0x12, 0x34]);
+
+        let mut reader = BitReader::new(&data[..], BitOrder::MsbFirst);
+        assert_eq!(reader.read_bits(2), 0b11);
+        assert!(!reader.is_aligned());
+        let mut bytes = reader.into_byte_reader();
+        assert_eq!(bytes.read_byte(), 0x12);
+
+        let mut reader = BitReader::from_byte_reader(bytes, BitOrder::LsbFirst);
+        assert_eq!(reader.bit_position(), 16);
+        assert_eq!(reader.read_bits(4), 0x4);
+        reader.align();
+        assert!(reader.is_read_finished());
+        assert_eq!(reader.try_read_bit(), Err(ReadError::UnexpectedEof { offset: 3, needed: 1, available: 0 }));
+
+        let reader = BitReader::new([0xFF; 2], BitOrder::MsbFirst);
+        assert_eq!(reader.peek_bits(17), Err(ReadError::UnexpectedEof { offset: 0, needed: 3, available: 2 }));
+        let mut reader = BitReader::new([0xFF; 16], BitOrder::MsbFirst);
+        reader.read_bits(3);
+        let too_many = Err(ReadError::InvalidValue { offset: 0, expected: "a bit count of at most 64" });
+        assert_eq!(reader.peek_bits(65), too_many);
+        assert_eq!(reader.try_read_bits(usize::MAX), too_many);
+        assert_eq!(reader.bit_position(), 3);
+
+        // Offsets inside a sub-reader count from the start of the whole input.
+        let mut bytes = ByteReader::new([0u8, 0, 0xF0, 0x0F]);
+        bytes.skip_bytes(1);
+        let mut sub = bytes.sub_reader(2).unwrap();
+        sub.skip_bytes(1);
+        let mut reader = BitReader::from_byte_reader(sub, BitOrder::MsbFirst);
+        assert_eq!(reader.read_bits(4), 0xF);
+        assert_eq!(reader.peek_bits(8), Err(ReadError::UnexpectedEof { offset: 2, needed: 2, available: 1 }));
+        assert_eq!(reader.peek_bits(65), Err(ReadError::InvalidValue { offset: 2, expected: "a bit count of at most 64" }));
+        let bytes = reader.into_byte_reader();
+        assert_eq!(bytes.peek_byte(), Err(ReadError::UnexpectedEof { offset: 3, needed: 1, available: 0 }));
+    }
+}
@@ -1,19 +1,81 @@
 use std::{fmt, io::{self, Read, Write}};
 
+use super::{Endian, LengthPrefix, Number};
+use super::varint::{zigzag_decode, zigzag_encode, MAX_VARINT_LEN};
+
+/// Error of the `try_*` and `peek_*` reads. Offsets count from the start of
+/// the outermost reader, also inside a [`ByteReader::sub_reader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadError {
+    /// `needed` bytes were wanted at `offset`, but only `available` were left.
+    UnexpectedEof { offset: usize, needed: usize, available: usize },
+    /// The varint at `offset` does not fit in a `u64`.
+    VarintOverflow { offset: usize },
+    /// The length prefix at `offset` announced `len` bytes, more than the `max` allowed.
+    TooLong { offset: usize, len: u64, max: usize },
+    /// The string starting at `offset` is not valid UTF-8.
+    InvalidUtf8 { offset: usize },
+    /// The bytes at `offset` are not a valid encoding of the `expected` value.
+    InvalidValue { offset: usize, expected: &'static str },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEof { offset, needed, available } => {
+                write!(f, "unexpected end of input at offset {offset}: needed {needed} bytes, {available} left")
+            }
+            Self::VarintOverflow { offset } => write!(f, "varint at offset {offset} overflows 64 bits"),
+            Self::TooLong { offset, len, max } => {
+                write!(f, "length {len} at offset {offset} exceeds the maximum of {max}")
+            }
+            Self::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 in string at offset {offset}"),
+            Self::InvalidValue { offset, expected } => write!(f, "invalid value at offset {offset}: expected {expected}"),
+        }
+    }
+}
 
+impl std::error::Error for ReadError {}
 
+impl From<ReadError> for io::Error {
+    fn from(e: ReadError) -> Self {
+        let kind = match e {
+            ReadError::UnexpectedEof { .. } => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Reads values from a byte slice.
+///
+/// The plain `read_*` methods panic when the input is too short, the
+/// `try_read_*` methods return a [`ReadError`] instead and leave the position
+/// unchanged, and `peek_*` reads without advancing.
 pub struct ByteReader<T> {
     inner: T,
-    pos: usize
+    pos: usize,
+    /// Offset of `inner` in the reader it was split from.
+    base: usize,
+    /// Levels of [`ByteReader::nested`] currently entered.
+    depth: usize,
 }
 
+/// Deepest [`ByteReader::nested`] nesting before decoding fails.
+pub const MAX_DEPTH: usize = 256;
+
 impl<T> ByteReader<T> {
     pub const fn new(inner: T) -> Self {
-        ByteReader { inner, pos: 0 }
+        ByteReader { inner, pos: 0, base: 0, depth: 0 }
     }
 
     pub const fn new_at(inner: T, position: usize) -> Self {
-        ByteReader { inner, pos: position }
+        ByteReader { inner, pos: position, base: 0, depth: 0 }
+    }
+
+    /// A reader whose `inner` starts at `base` of the outermost one.
+    pub(super) const fn with_base(inner: T, position: usize, base: usize) -> Self {
+        ByteReader { inner, pos: position, base, depth: 0 }
     }
 
     pub fn skip_bytes(&mut self, amount: usize) {
@@ -28,6 +90,31 @@ impl<T> ByteReader<T> {
         self.pos = position;
     }
 
+    /// The position counted from the start of the outermost reader, as
+    /// reported in a [`ReadError`].
+    pub const fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+
+    /// An [`ReadError::InvalidValue`] at the current position.
+    pub const fn invalid(&self, expected: &'static str) -> ReadError {
+        ReadError::InvalidValue { offset: self.offset(), expected }
+    }
+
+    /// Runs `decode` one level deeper, or fails with a
+    /// [`ReadError::InvalidValue`] beyond [`MAX_DEPTH`] levels. Decoders of
+    /// recursive types go through this, so hostile input can't nest them
+    /// until the stack overflows.
+    pub fn nested<V>(&mut self, decode: impl FnOnce(&mut Self) -> Result<V, ReadError>) -> Result<V, ReadError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.invalid("at most 256 levels of nesting"));
+        }
+        self.depth += 1;
+        let result = decode(self);
+        self.depth -= 1;
+        result
+    }
+
     pub fn into_inner(self) -> T {
         self.inner
     }
@@ -37,68 +124,297 @@ impl<T> ByteReader<T> {
     }
 }
 
+/// Unwraps the result of a `try_*` read for the panicking variants.
+fn or_panic<V>(result: Result<V, ReadError>) -> V {
+    result.unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// The panicking, `try_` and `peek_` variants of fixed-size number reads.
+macro_rules! read_numbers {
+    ($($ty:ty, $endian:expr => $read:ident, $try_read:ident, $peek:ident;)*) => {$(
+        pub fn $read(&mut self) -> $ty {
+            or_panic(self.$try_read())
+        }
+
+        pub fn $try_read(&mut self) -> Result<$ty, ReadError> {
+            self.try_read_number($endian)
+        }
+
+        pub fn $peek(&self) -> Result<$ty, ReadError> {
+            self.peek_number($endian)
+        }
+    )*};
+}
+
 impl<T> ByteReader<T>
 where T: AsRef<[u8]> {
 
-    pub fn read_byte(&mut self) -> u8 {
-        self.pos += 1;
-        self.inner.as_ref()[self.pos - 1]
+    /// Bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.inner.as_ref().len().saturating_sub(self.pos)
     }
 
-    pub fn read_bytes(&mut self, len: usize) -> Vec<u8> {
+    fn eof(&self, needed: usize) -> ReadError {
+        ReadError::UnexpectedEof { offset: self.offset(), needed, available: self.remaining() }
+    }
+
+    /// The next `len` bytes, without advancing.
+    pub fn peek_bytes(&self, len: usize) -> Result<&[u8], ReadError> {
+        self.remaining_bytes().get(..len).ok_or_else(|| self.eof(len))
+    }
+
+    pub fn peek_byte(&self) -> Result<u8, ReadError> {
+        self.peek_bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn peek_array<const N: usize>(&self) -> Result<[u8; N], ReadError> {
+        self.remaining_bytes().first_chunk().copied().ok_or_else(|| self.eof(N))
+    }
+
+    /// Borrows the next `len` bytes and advances past them.
+    pub fn try_read_slice(&mut self, len: usize) -> Result<&[u8], ReadError> {
         let start = self.pos;
+        self.peek_bytes(len)?;
         self.pos += len;
-        self.inner.as_ref()[start..self.pos].to_vec()
+        Ok(&self.inner.as_ref()[start..self.pos])
     }
 
+    pub fn try_skip_bytes(&mut self, amount: usize) -> Result<(), ReadError> {
+        self.try_read_slice(amount).map(|_| ())
+    }
+
+    pub fn read_byte(&mut self) -> u8 {
+        or_panic(self.try_read_byte())
+    }
+
+    pub fn try_read_byte(&mut self) -> Result<u8, ReadError> {
+        self.try_read_u8()
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Vec<u8> {
+        or_panic(self.try_read_bytes(len))
+    }
+
+    pub fn try_read_bytes(&mut self, len: usize) -> Result<Vec<u8>, ReadError> {
+        self.try_read_slice(len).map(<[u8]>::to_vec)
+    }
+
+    /// Invalid UTF-8 is replaced, as with [`String::from_utf8_lossy`].
     pub fn read_string(&mut self, len: usize) -> String {
-        let start = self.pos;
-        self.pos += len;
-        String::from_utf8_lossy(&self.inner.as_ref()[start..self.pos]).to_string()
+        or_panic(self.try_read_string(len))
+    }
+
+    pub fn try_read_string(&mut self, len: usize) -> Result<String, ReadError> {
+        self.try_read_slice(len).map(|bytes| String::from_utf8_lossy(bytes).into_owned())
     }
 
     pub fn read_bool(&mut self) -> bool {
-        self.pos += 1;
-        self.inner.as_ref()[self.pos - 1] != 0
+        or_panic(self.try_read_bool())
     }
 
-    pub fn read_be_u16(&mut self) -> u16 {
-        self.pos += 2;
-        u16::from_be_bytes([self.inner.as_ref()[self.pos - 2], self.inner.as_ref()[self.pos - 1]])
+    pub fn try_read_bool(&mut self) -> Result<bool, ReadError> {
+        self.try_read_byte().map(|byte| byte != 0)
     }
 
-    pub fn read_le_u24(&mut self) -> u32 {
+    pub fn peek_bool(&self) -> Result<bool, ReadError> {
+        self.peek_byte().map(|byte| byte != 0)
+    }
+
+    /// Reads any [`Number`] in the given byte order.
+    pub fn read_number<N: Number>(&mut self, endian: Endian) -> N {
+        or_panic(self.try_read_number(endian))
+    }
+
+    pub fn try_read_number<N: Number>(&mut self, endian: Endian) -> Result<N, ReadError> {
+        self.try_read_slice(N::SIZE).map(|bytes| N::from_bytes(bytes, endian))
+    }
+
+    pub fn peek_number<N: Number>(&self, endian: Endian) -> Result<N, ReadError> {
+        self.peek_bytes(N::SIZE).map(|bytes| N::from_bytes(bytes, endian))
+    }
+
+    read_numbers! {
+        u8, Endian::Big => read_u8, try_read_u8, peek_u8;
+        i8, Endian::Big => read_i8, try_read_i8, peek_i8;
+
+        u16, Endian::Big => read_be_u16, try_read_be_u16, peek_be_u16;
+        u32, Endian::Big => read_be_u32, try_read_be_u32, peek_be_u32;
+        u64, Endian::Big => read_be_u64, try_read_be_u64, peek_be_u64;
+        u128, Endian::Big => read_be_u128, try_read_be_u128, peek_be_u128;
+        i16, Endian::Big => read_be_i16, try_read_be_i16, peek_be_i16;
+        i32, Endian::Big => read_be_i32, try_read_be_i32, peek_be_i32;
+        i64, Endian::Big => read_be_i64, try_read_be_i64, peek_be_i64;
+        i128, Endian::Big => read_be_i128, try_read_be_i128, peek_be_i128;
+        f32, Endian::Big => read_be_f32, try_read_be_f32, peek_be_f32;
+        f64, Endian::Big => read_be_f64, try_read_be_f64, peek_be_f64;
+
+        u16, Endian::Little => read_le_u16, try_read_le_u16, peek_le_u16;
+        u32, Endian::Little => read_le_u32, try_read_le_u32, peek_le_u32;
+        u64, Endian::Little => read_le_u64, try_read_le_u64, peek_le_u64;
+        u128, Endian::Little => read_le_u128, try_read_le_u128, peek_le_u128;
+        i16, Endian::Little => read_le_i16, try_read_le_i16, peek_le_i16;
+        i32, Endian::Little => read_le_i32, try_read_le_i32, peek_le_i32;
+        i64, Endian::Little => read_le_i64, try_read_le_i64, peek_le_i64;
+        i128, Endian::Little => read_le_i128, try_read_le_i128, peek_le_i128;
+        f32, Endian::Little => read_le_f32, try_read_le_f32, peek_le_f32;
+        f64, Endian::Little => read_le_f64, try_read_le_f64, peek_le_f64;
+    }
+
+    /// Reads a 24-bit number in the given byte order.
+    pub fn try_read_u24(&mut self, endian: Endian) -> Result<u32, ReadError> {
+        let value = self.peek_u24(endian)?;
         self.pos += 3;
-        u32::from_le_bytes([self.inner.as_ref()[self.pos - 3], self.inner.as_ref()[self.pos - 2], self.inner.as_ref()[self.pos - 1], 0])
+        Ok(value)
     }
 
-    pub fn read_be_u32(&mut self) -> u32 {
-        self.pos += 4;
-        u32::from_be_bytes([self.inner.as_ref()[self.pos - 4], self.inner.as_ref()[self.pos - 3], self.inner.as_ref()[self.pos - 2], self.inner.as_ref()[self.pos - 1]])
+    pub fn peek_u24(&self, endian: Endian) -> Result<u32, ReadError> {
+        let [a, b, c] = self.peek_array()?;
+        Ok(match endian {
+            Endian::Big => u32::from_be_bytes([0, a, b, c]),
+            Endian::Little => u32::from_le_bytes([a, b, c, 0]),
+        })
     }
 
-    pub fn read_be_u64(&mut self) -> u64 {
-        self.pos += 8;
-        u64::from_be_bytes([self.inner.as_ref()[self.pos - 8], self.inner.as_ref()[self.pos - 7], self.inner.as_ref()[self.pos - 6], self.inner.as_ref()[self.pos - 5], self.inner.as_ref()[self.pos - 4], self.inner.as_ref()[self.pos - 3], self.inner.as_ref()[self.pos - 2], self.inner.as_ref()[self.pos - 1]])
+    pub fn read_be_u24(&mut self) -> u32 {
+        or_panic(self.try_read_u24(Endian::Big))
     }
 
-    pub fn read_be_i64(&mut self) -> i64 {
-        self.pos += 8;
-        i64::from_be_bytes([self.inner.as_ref()[self.pos - 8], self.inner.as_ref()[self.pos - 7], self.inner.as_ref()[self.pos - 6], self.inner.as_ref()[self.pos - 5], self.inner.as_ref()[self.pos - 4], self.inner.as_ref()[self.pos - 3], self.inner.as_ref()[self.pos - 2], self.inner.as_ref()[self.pos - 1]])
+    pub fn try_read_be_u24(&mut self) -> Result<u32, ReadError> {
+        self.try_read_u24(Endian::Big)
     }
 
-    pub fn read_le_u16(&mut self) -> u16 {
-        self.pos += 2;
-        u16::from_le_bytes([self.inner.as_ref()[self.pos - 2], self.inner.as_ref()[self.pos - 1]])
+    pub fn peek_be_u24(&self) -> Result<u32, ReadError> {
+        self.peek_u24(Endian::Big)
     }
 
-    pub fn read_le_u32(&mut self) -> u32 {
-        self.pos += 4;
-        u32::from_le_bytes([self.inner.as_ref()[self.pos - 4], self.inner.as_ref()[self.pos - 3], self.inner.as_ref()[self.pos - 2], self.inner.as_ref()[self.pos - 1]])
+    pub fn read_le_u24(&mut self) -> u32 {
+        or_panic(self.try_read_u24(Endian::Little))
+    }
+
+    pub fn try_read_le_u24(&mut self) -> Result<u32, ReadError> {
+        self.try_read_u24(Endian::Little)
+    }
+
+    pub fn peek_le_u24(&self) -> Result<u32, ReadError> {
+        self.peek_u24(Endian::Little)
+    }
+
+    /// Reads an unsigned LEB128 varint.
+    pub fn read_varint(&mut self) -> u64 {
+        or_panic(self.try_read_varint())
+    }
+
+    pub fn try_read_varint(&mut self) -> Result<u64, ReadError> {
+        let (value, len) = self.peek_varint()?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    /// The next varint and its encoded length, without advancing.
+    fn peek_varint(&self) -> Result<(u64, usize), ReadError> {
+        let mut value = 0;
+        for (i, &byte) in self.remaining_bytes().iter().take(MAX_VARINT_LEN).enumerate() {
+            // The tenth byte only has room for the top bit of a u64.
+            if i == MAX_VARINT_LEN - 1 && byte > 1 {
+                return Err(ReadError::VarintOverflow { offset: self.offset() });
+            }
+            value |= u64::from(byte & 0x7F) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok((value, i + 1));
+            }
+        }
+        match self.remaining() {
+            available if available < MAX_VARINT_LEN => Err(self.eof(available + 1)),
+            _ => Err(ReadError::VarintOverflow { offset: self.offset() }),
+        }
+    }
+
+    /// Reads a zigzag-encoded signed varint.
+    pub fn read_zigzag_varint(&mut self) -> i64 {
+        or_panic(self.try_read_zigzag_varint())
+    }
+
+    pub fn try_read_zigzag_varint(&mut self) -> Result<i64, ReadError> {
+        self.try_read_varint().map(zigzag_decode)
+    }
+
+    /// Borrows a blob preceded by its length, refusing lengths above `max`.
+    pub fn try_read_prefixed_slice(&mut self, prefix: LengthPrefix, max: usize) -> Result<&[u8], ReadError> {
+        let (header, len) = self.peek_prefixed(prefix, max)?;
+        self.pos += header;
+        self.try_read_slice(len)
+    }
+
+    /// The size of the length prefix and the length it holds, checked
+    /// against `max` and the remaining input.
+    fn peek_prefixed(&self, prefix: LengthPrefix, max: usize) -> Result<(usize, usize), ReadError> {
+        let (len, header) = match prefix {
+            LengthPrefix::U16(endian) => (self.peek_number::<u16>(endian)?.into(), 2),
+            LengthPrefix::U32(endian) => (self.peek_number::<u32>(endian)?.into(), 4),
+            LengthPrefix::Varint => self.peek_varint()?,
+        };
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= max)
+            .ok_or(ReadError::TooLong { offset: self.offset(), len, max })?;
+        // `header + len` could overflow with a large `max`.
+        if len > self.remaining() - header {
+            return Err(self.eof(header.saturating_add(len)));
+        }
+        Ok((header, len))
+    }
+
+    pub fn read_prefixed_bytes(&mut self, prefix: LengthPrefix, max: usize) -> Vec<u8> {
+        or_panic(self.try_read_prefixed_bytes(prefix, max))
+    }
+
+    pub fn try_read_prefixed_bytes(&mut self, prefix: LengthPrefix, max: usize) -> Result<Vec<u8>, ReadError> {
+        self.try_read_prefixed_slice(prefix, max).map(<[u8]>::to_vec)
+    }
+
+    /// Reads a length-prefixed string of at most `max` bytes. Unlike
+    /// [`ByteReader::read_string`], invalid UTF-8 is an error.
+    pub fn read_prefixed_string(&mut self, prefix: LengthPrefix, max: usize) -> String {
+        or_panic(self.try_read_prefixed_string(prefix, max))
+    }
+
+    pub fn try_read_prefixed_string(&mut self, prefix: LengthPrefix, max: usize) -> Result<String, ReadError> {
+        let (header, len) = self.peek_prefixed(prefix, max)?;
+        let string = std::str::from_utf8(&self.remaining_bytes()[header..header + len])
+            .map_err(|_| ReadError::InvalidUtf8 { offset: self.offset() + header })?
+            .to_owned();
+        self.pos += header + len;
+        Ok(string)
+    }
+
+    /// Reads a NUL-terminated string and skips the terminator. Invalid UTF-8
+    /// is an error.
+    pub fn read_cstring(&mut self) -> String {
+        or_panic(self.try_read_cstring())
+    }
+
+    pub fn try_read_cstring(&mut self) -> Result<String, ReadError> {
+        let remaining = self.remaining_bytes();
+        let Some(len) = remaining.iter().position(|&byte| byte == 0) else {
+            return Err(self.eof(remaining.len() + 1));
+        };
+        let string = std::str::from_utf8(&remaining[..len])
+            .map_err(|_| ReadError::InvalidUtf8 { offset: self.offset() })?
+            .to_owned();
+        self.pos += len + 1;
+        Ok(string)
+    }
+
+    /// Splits off the next `len` bytes as a reader of their own and advances
+    /// past them, so a length-prefixed region can't be overrun.
+    pub fn sub_reader(&mut self, len: usize) -> Result<ByteReader<&[u8]>, ReadError> {
+        let (base, depth) = (self.base + self.pos, self.depth);
+        let region = self.try_read_slice(len)?;
+        Ok(ByteReader { inner: region, pos: 0, base, depth })
     }
 
     pub fn remaining_bytes(&self) -> &[u8] {
-        &self.inner.as_ref()[self.pos..]
+        self.inner.as_ref().get(self.pos..).unwrap_or_default()
     }
 
     pub fn is_read_finished(&self) -> bool {
@@ -111,9 +427,8 @@ where
     T: AsRef<[u8]>,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let len = buf.len();
-        let remaining = &self.inner.as_ref()[self.pos..];
-        let to_read = remaining.len().min(len);
+        let remaining = self.remaining_bytes();
+        let to_read = remaining.len().min(buf.len());
         buf[..to_read].copy_from_slice(&remaining[..to_read]);
         self.pos += to_read;
         Ok(to_read)
@@ -123,23 +438,28 @@ where
 impl<T> ByteReader<T> 
 where T: AsRef<Vec<u8>> {
     pub fn get_remaining(&self) -> &[u8] {
-        &self.inner.as_ref()[self.pos..self.inner.as_ref().len() - 1]
+        self.inner.as_ref().get(self.pos..).unwrap_or_default()
     }
 }
 
-impl <T>  ByteReader<T> 
-where T: AsRef<[u8]> {}
-
-impl<T: std::fmt::Debug> std::fmt::Debug for ByteReader<T> 
+impl<T> std::fmt::Debug for ByteReader<T> 
 where T: AsRef<[u8]> {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ByteReader {{ inner: {:?}, position: {} }}", self.inner.as_ref()[self.pos], self.pos)
+        write!(f, "ByteReader {{ len: {}, position: {} }}", self.inner.as_ref().len(), self.pos)
     }
 }
 
 
 
+macro_rules! write_numbers {
+    ($($ty:ty, $endian:expr => $write:ident;)*) => {$(
+        pub fn $write(&mut self, number: $ty) {
+            self.write_number(number, $endian);
+        }
+    )*};
+}
+
 pub struct ByteWriter {
     buf: Vec<u8>
 }
@@ -182,50 +502,124 @@ impl ByteWriter {
         self.buf.push(bool as u8);
     }
 
+    /// Writes any [`Number`] in the given byte order.
+    pub fn write_number<N: Number>(&mut self, number: N, endian: Endian) {
+        number.write_bytes(&mut self.buf, endian);
+    }
+
+    write_numbers! {
+        u8, Endian::Big => write_u8;
+        i8, Endian::Big => write_i8;
+
+        u16, Endian::Big => write_be_u16;
+        u32, Endian::Big => write_be_u32;
+        u64, Endian::Big => write_be_u64;
+        u128, Endian::Big => write_be_u128;
+        i16, Endian::Big => write_be_i16;
+        i32, Endian::Big => write_be_i32;
+        i64, Endian::Big => write_be_i64;
+        i128, Endian::Big => write_be_i128;
+        f32, Endian::Big => write_be_f32;
+        f64, Endian::Big => write_be_f64;
+
+        u16, Endian::Little => write_le_u16;
+        u32, Endian::Little => write_le_u32;
+        u64, Endian::Little => write_le_u64;
+        u128, Endian::Little => write_le_u128;
+        i16, Endian::Little => write_le_i16;
+        i32, Endian::Little => write_le_i32;
+        i64, Endian::Little => write_le_i64;
+        i128, Endian::Little => write_le_i128;
+        f32, Endian::Little => write_le_f32;
+        f64, Endian::Little => write_le_f64;
+    }
+
+    /// Writes the low 24 bits of `number` in the given byte order.
+    pub fn write_u24(&mut self, number: u32, endian: Endian) {
+        match endian {
+            Endian::Big => self.buf.extend_from_slice(&number.to_be_bytes()[1..]),
+            Endian::Little => self.buf.extend_from_slice(&number.to_le_bytes()[..3]),
+        }
+    }
+
+    #[deprecated(note = "writes little-endian, use `write_le_u16`")]
     pub fn write_u16(&mut self, number: u16) {
-        self.buf.extend(number.to_le_bytes());
+        self.write_le_u16(number);
     }
 
+    #[deprecated(note = "writes little-endian, use `write_le_u32`")]
     pub fn write_u32(&mut self, number: u32) {
-        self.buf.extend(number.to_le_bytes());
+        self.write_le_u32(number);
     }
 
-    pub fn write_u24(&mut self, number: u32) {
-        let bytes = number.to_le_bytes();
-        self.buf.extend_from_slice(&[bytes[0], bytes[1], bytes [2]]);
+    #[deprecated(note = "writes little-endian, use `write_le_u64`")]
+    pub fn write_u64(&mut self, number: u64) {
+        self.write_le_u64(number);
+    }
+
+    #[deprecated(note = "writes little-endian, use `write_le_f32`")]
+    pub fn write_f32(&mut self, number: f32) {
+        self.write_le_f32(number);
     }
 
     pub fn write_be_u24(&mut self, number: u32) {
-        let bytes = number.to_be_bytes();
-        self.buf.extend_from_slice(&[bytes[0], bytes[1], bytes [2], 0]);
+        self.write_u24(number, Endian::Big);
     }
 
-    pub fn write_u64(&mut self, number: u64) {
-        self.buf.extend(number.to_le_bytes());
+    pub fn write_le_u24(&mut self, number: u32) {
+        self.write_u24(number, Endian::Little);
     }
 
-    pub fn write_be_u16(&mut self, number: u16) {
-        self.buf.extend(number.to_be_bytes());
+    pub fn write_string(&mut self, string: &str) {
+        self.buf.extend_from_slice(string.as_bytes());
     }
 
-    pub fn write_be_u32(&mut self, number: u32) {
-        self.buf.extend(number.to_be_bytes());
+    /// Writes an unsigned LEB128 varint.
+    pub fn write_varint(&mut self, mut number: u64) {
+        while number >= 0x80 {
+            self.buf.push(number as u8 | 0x80);
+            number >>= 7;
+        }
+        self.buf.push(number as u8);
     }
 
-    pub fn write_be_u64(&mut self, number: u64) {
-        self.buf.extend(number.to_be_bytes());
+    /// Writes a zigzag-encoded signed varint.
+    pub fn write_zigzag_varint(&mut self, number: i64) {
+        self.write_varint(zigzag_encode(number));
     }
 
-    pub fn write_be_i64(&mut self, number: i64) {
-        self.buf.extend(number.to_be_bytes());
+    /// Writes `bytes` preceded by their length.
+    ///
+    /// Panics if the length doesn't fit in the prefix.
+    pub fn write_prefixed_bytes(&mut self, prefix: LengthPrefix, bytes: &[u8]) {
+        let len = bytes.len() as u64;
+        assert!(len <= prefix.max_len(), "length {len} does not fit in a {prefix:?} prefix");
+        match prefix {
+            LengthPrefix::U16(endian) => self.write_number(len as u16, endian),
+            LengthPrefix::U32(endian) => self.write_number(len as u32, endian),
+            LengthPrefix::Varint => self.write_varint(len),
+        }
+        self.buf.extend_from_slice(bytes);
     }
 
-    pub fn write_f32(&mut self, number: f32){
-        self.buf.extend(number.to_le_bytes());
+    pub fn write_prefixed_string(&mut self, prefix: LengthPrefix, string: &str) {
+        self.write_prefixed_bytes(prefix, string.as_bytes());
     }
 
-    pub fn write_string(&mut self, string: &str) {
+    /// Writes `string` followed by a NUL byte.
+    ///
+    /// Panics if `string` itself contains a NUL.
+    pub fn write_cstring(&mut self, string: &str) {
+        assert!(!string.contains('\0'), "C string contains a NUL byte");
         self.buf.extend_from_slice(string.as_bytes());
+        self.buf.push(0);
+    }
+}
+
+impl From<Vec<u8>> for ByteWriter {
+    /// Appends to `buf`.
+    fn from(buf: Vec<u8>) -> Self {
+        ByteWriter { buf }
     }
 }
 
@@ -242,4 +636,216 @@ impl Write for ByteWriter {
     fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallible_reads_report_the_offset() {
+        let data = [0x12, 0x34, 0x56, 0x78, 0x9A];
+        let mut reader = ByteReader::new(&data[..]);
+        assert_eq!(reader.peek_be_u16(), Ok(0x1234));
+        assert_eq!(reader.try_read_be_u32(), Ok(0x1234_5678));
+        assert_eq!(reader.try_read_le_u16(), Err(ReadError::UnexpectedEof { offset: 4, needed: 2, available: 1 }));
+        // A failed read leaves the position alone.
+        assert_eq!(reader.position(), 4);
+        assert_eq!(reader.peek_byte(), Ok(0x9A));
+        assert_eq!(reader.read_byte(), 0x9A);
+        assert!(reader.try_read_bool().is_err());
+
+        // Reads past the end report the error instead of indexing out of bounds.
+        reader.skip_bytes(3);
+        assert_eq!(reader.remaining_bytes(), &[] as &[u8]);
+        assert_eq!(reader.try_read_byte(), Err(ReadError::UnexpectedEof { offset: 8, needed: 1, available: 0 }));
+        let error: io::Error = reader.try_read_byte().unwrap_err().into();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        assert_eq!(ByteReader::new(vec![1, 2, 3]).get_remaining(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn sub_readers_stay_inside_their_region() {
+        let data = [3, b'a', b'b', b'c', 0xFF];
+        let mut reader = ByteReader::new(data);
+        let len = reader.read_byte() as usize;
+        let mut region = reader.sub_reader(len).unwrap();
+        assert_eq!(region.try_read_string(3).as_deref(), Ok("abc"));
+        assert_eq!(region.try_read_byte(), Err(ReadError::UnexpectedEof { offset: 4, needed: 1, available: 0 }));
+        assert_eq!(reader.read_byte(), 0xFF);
+        assert!(reader.sub_reader(1).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected end of input at offset 1")]
+    fn plain_reads_panic_with_the_error() {
+        let mut reader = ByteReader::new([1]);
+        reader.read_byte();
+        reader.read_le_u24();
+    }
+
+    #[test]
+    fn varints_and_prefixed_values_round_trip() {
+        let mut writer = ByteWriter::new();
+        for number in [0, 1, 127, 128, 300, u64::MAX] {
+            writer.write_varint(number);
+        }
+        for number in [0, -1, 1, -64, 64, i64::MIN, i64::MAX] {
+            writer.write_zigzag_varint(number);
+        }
+        writer.write_prefixed_string(LengthPrefix::U16(Endian::Big), "héllo");
+        writer.write_prefixed_bytes(LengthPrefix::U32(Endian::Little), &[1, 2, 3]);
+        writer.write_prefixed_string(LengthPrefix::Varint, &"x".repeat(200));
+        writer.write_cstring("path/to/file");
+        let data = writer.finish();
+        assert_eq!(data[..5], [0, 1, 0x7F, 0x80, 0x01]);
+
+        let mut reader = ByteReader::new(&data[..]);
+        for number in [0, 1, 127, 128, 300, u64::MAX] {
+            assert_eq!(reader.try_read_varint(), Ok(number));
+        }
+        for number in [0, -1, 1, -64, 64, i64::MIN, i64::MAX] {
+            assert_eq!(reader.read_zigzag_varint(), number);
+        }
+        assert_eq!(reader.read_prefixed_string(LengthPrefix::U16(Endian::Big), 16), "héllo");
+        assert_eq!(reader.read_prefixed_bytes(LengthPrefix::U32(Endian::Little), 16), [1, 2, 3]);
+        let offset = reader.position();
+        assert_eq!(
+            reader.try_read_prefixed_string(LengthPrefix::Varint, 100),
+            Err(ReadError::TooLong { offset, len: 200, max: 100 }),
+        );
+        assert_eq!(reader.position(), offset);
+        assert_eq!(reader.read_prefixed_string(LengthPrefix::Varint, 200).len(), 200);
+        assert_eq!(reader.read_cstring(), "path/to/file");
+        assert!(reader.is_read_finished());
+    }
+
+    #[test]
+    fn malformed_varints_and_strings_are_rejected() {
+        let mut reader = ByteReader::new([0xFF; 11]);
+        assert_eq!(reader.try_read_varint(), Err(ReadError::VarintOverflow { offset: 0 }));
+        let mut reader = ByteReader::new([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02]);
+        assert_eq!(reader.try_read_varint(), Err(ReadError::VarintOverflow { offset: 0 }));
+        let mut reader = ByteReader::new([0x80, 0x80]);
+        assert_eq!(reader.try_read_varint(), Err(ReadError::UnexpectedEof { offset: 0, needed: 3, available: 2 }));
+
+        let mut reader = ByteReader::new([0, 5, b'a', b'b']);
+        assert_eq!(
+            reader.try_read_prefixed_bytes(LengthPrefix::U16(Endian::Big), 10),
+            Err(ReadError::UnexpectedEof { offset: 0, needed: 7, available: 4 }),
+        );
+        let mut reader = ByteReader::new([2, 0xC3, 0x28, 0, b'o', b'k']);
+        assert_eq!(reader.try_read_prefixed_string(LengthPrefix::Varint, 10), Err(ReadError::InvalidUtf8 { offset: 1 }));
+        let mut huge = ByteWriter::new();
+        huge.write_varint(u64::MAX);
+        huge.write_bytes(b"abc");
+        let huge = huge.finish();
+        assert_eq!(huge.len(), 13);
+        let mut huge = ByteReader::new(&huge[..]);
+        assert_eq!(
+            huge.try_read_prefixed_bytes(LengthPrefix::Varint, usize::MAX),
+            Err(ReadError::UnexpectedEof { offset: 0, needed: usize::MAX, available: 13 }),
+        );
+        assert!(huge.try_read_prefixed_string(LengthPrefix::Varint, usize::MAX).is_err());
+        assert_eq!(huge.position(), 0);
+        assert_eq!(reader.position(), 0);
+        assert_eq!(reader.try_read_cstring(), Err(ReadError::InvalidUtf8 { offset: 0 }));
+        reader.skip_bytes(4);
+        assert_eq!(reader.try_read_cstring(), Err(ReadError::UnexpectedEof { offset: 4, needed: 3, available: 2 }));
+        let error: io::Error = reader.try_read_cstring().unwrap_err().into();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let error: io::Error = ReadError::InvalidUtf8 { offset: 0 }.into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Random values of every width, from `rand::hash`.
+    fn samples(count: u32) -> impl Iterator<Item = u128> {
+        (0..count).map(|i| {
+            let word = |n| crate::rand::hash(i.wrapping_mul(4).wrapping_add(n)) as u128;
+            let value = word(0) | word(1) << 32 | word(2) << 64 | word(3) << 96;
+            // Also cover the extremes of each type.
+            match i {
+                0 => 0,
+                1 => u128::MAX,
+                _ => value,
+            }
+        })
+    }
+
+    #[test]
+    fn every_number_round_trips_in_both_byte_orders() {
+        macro_rules! round_trip {
+            ($value:expr, $($ty:ty: $write_be:ident $write_le:ident $read_be:ident $read_le:ident $peek_be:ident $peek_le:ident;)*) => {$({
+                let value = $value as $ty;
+                let mut writer = ByteWriter::new();
+                writer.$write_be(value);
+                writer.$write_le(value);
+                writer.write_number(value, Endian::NATIVE);
+                let size = size_of::<$ty>();
+                assert_eq!(writer.as_ref()[..size], value.to_be_bytes());
+                assert_eq!(writer.as_ref()[size..2 * size], value.to_le_bytes());
+
+                let mut reader = ByteReader::new(writer.finish());
+                assert_eq!(reader.$peek_be().map(<$ty>::to_bits), Ok(value.to_bits()));
+                assert_eq!(reader.$read_be().to_bits(), value.to_bits());
+                assert_eq!(reader.$peek_le().map(<$ty>::to_bits), Ok(value.to_bits()));
+                assert_eq!(reader.$read_le().to_bits(), value.to_bits());
+                assert_eq!(reader.read_number::<$ty>(Endian::NATIVE).to_bits(), value.to_bits());
+                assert!(reader.is_read_finished());
+            })*};
+        }
+
+        /// Integers compare directly, floats by their bits because of NaN.
+        trait Bits: Copy {
+            type Bits: PartialEq + fmt::Debug;
+            fn to_bits(self) -> Self::Bits;
+        }
+        macro_rules! bits {
+            ($($ty:ty => $bits:ty, $convert:expr;)*) => {$(
+                impl Bits for $ty {
+                    type Bits = $bits;
+                    fn to_bits(self) -> $bits {
+                        $convert(self)
+                    }
+                }
+            )*};
+        }
+        bits! {
+            u16 => u16, |v| v; u32 => u32, |v| v; u64 => u64, |v| v; u128 => u128, |v| v;
+            i16 => i16, |v| v; i32 => i32, |v| v; i64 => i64, |v| v; i128 => i128, |v| v;
+            f32 => u32, f32::to_bits; f64 => u64, f64::to_bits;
+        }
+
+        for sample in samples(200) {
+            round_trip! { sample,
+                u16: write_be_u16 write_le_u16 read_be_u16 read_le_u16 peek_be_u16 peek_le_u16;
+                u32: write_be_u32 write_le_u32 read_be_u32 read_le_u32 peek_be_u32 peek_le_u32;
+                u64: write_be_u64 write_le_u64 read_be_u64 read_le_u64 peek_be_u64 peek_le_u64;
+                u128: write_be_u128 write_le_u128 read_be_u128 read_le_u128 peek_be_u128 peek_le_u128;
+                i16: write_be_i16 write_le_i16 read_be_i16 read_le_i16 peek_be_i16 peek_le_i16;
+                i32: write_be_i32 write_le_i32 read_be_i32 read_le_i32 peek_be_i32 peek_le_i32;
+                i64: write_be_i64 write_le_i64 read_be_i64 read_le_i64 peek_be_i64 peek_le_i64;
+                i128: write_be_i128 write_le_i128 read_be_i128 read_le_i128 peek_be_i128 peek_le_i128;
+            }
+            round_trip! { f32::from_bits(sample as u32),
+                f32: write_be_f32 write_le_f32 read_be_f32 read_le_f32 peek_be_f32 peek_le_f32;
+            }
+            round_trip! { f64::from_bits(sample as u64),
+                f64: write_be_f64 write_le_f64 read_be_f64 read_le_f64 peek_be_f64 peek_le_f64;
+            }
+
+            let (byte, u24) = (sample as u8, sample as u32 & 0xFF_FFFF);
+            let mut writer = ByteWriter::new();
+            writer.write_u8(byte);
+            writer.write_i8(byte as i8);
+            writer.write_be_u24(u24);
+            writer.write_le_u24(u24);
+            assert_eq!(writer.len(), 8);
+            let mut reader = ByteReader::new(writer.finish());
+            assert_eq!((reader.read_u8(), reader.read_i8()), (byte, byte as i8));
+            assert_eq!(reader.peek_be_u24(), Ok(u24));
+            assert_eq!((reader.read_be_u24(), reader.read_le_u24()), (u24, u24));
+        }
+    }
+}
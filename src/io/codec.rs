@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::{ByteReader, ByteWriter, Endian, LengthPrefix, ReadError};
+use crate::primitives::{Vec2, Vec3, Vec4};
+
+/// A value that can be written to a [`ByteWriter`].
+///
+/// Numbers are little-endian, `usize`/`isize` and all lengths are varints,
+/// `bool` and `Option` tags are one byte. `#[derive(Encode)]` is available
+/// with the `derive` feature; enum variants are written as their index.
+pub trait Encode {
+    fn encode(&self, writer: &mut ByteWriter);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        self.encode(&mut writer);
+        writer.finish()
+    }
+}
+
+/// A value that can be read back from what [`Encode`] wrote.
+///
+/// Unlike the `try_read_*` methods, a failed decode may leave the reader
+/// anywhere inside the value.
+///
+/// Input may be untrusted: a collection can't announce more items than there
+/// are bytes left, which also caps collections of zero-sized items like `()`.
+/// `Box` and derived decoders count as a level of [`ByteReader::nested`], so
+/// recursive types fail past [`MAX_DEPTH`](super::MAX_DEPTH) levels instead
+/// of overflowing the stack. Hand-written decoders of recursive types should
+/// do the same.
+pub trait Decode: Sized {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError>;
+
+    /// Decodes a value that must span all of `bytes`.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ReadError> {
+        let mut reader = ByteReader::new(bytes);
+        let value = Self::decode(&mut reader)?;
+        match reader.is_read_finished() {
+            true => Ok(value),
+            false => Err(reader.invalid("end of input")),
+        }
+    }
+}
+
+macro_rules! numbers {
+    ($($ty:ty)*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, writer: &mut ByteWriter) {
+                writer.write_number(*self, Endian::Little);
+            }
+        }
+
+        impl Decode for $ty {
+            fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+                reader.try_read_number(Endian::Little)
+            }
+        }
+    )*};
+}
+
+numbers!(u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64);
+
+impl Encode for usize {
+    fn encode(&self, writer: &mut ByteWriter) {
+        writer.write_varint(*self as u64);
+    }
+}
+
+impl Decode for usize {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        let offset = reader.offset();
+        let value = reader.try_read_varint()?;
+        usize::try_from(value).map_err(|_| ReadError::InvalidValue { offset, expected: "a usize" })
+    }
+}
+
+impl Encode for isize {
+    fn encode(&self, writer: &mut ByteWriter) {
+        writer.write_zigzag_varint(*self as i64);
+    }
+}
+
+impl Decode for isize {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        let offset = reader.offset();
+        let value = reader.try_read_zigzag_varint()?;
+        isize::try_from(value).map_err(|_| ReadError::InvalidValue { offset, expected: "an isize" })
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, writer: &mut ByteWriter) {
+        writer.write_bool(*self);
+    }
+}
+
+impl Decode for bool {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        match reader.peek_byte()? {
+            0 | 1 => reader.try_read_bool(),
+            _ => Err(reader.invalid("a bool")),
+        }
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, writer: &mut ByteWriter) {
+        (*self as u32).encode(writer);
+    }
+}
+
+impl Decode for char {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        let offset = reader.offset();
+        char::from_u32(u32::decode(reader)?).ok_or(ReadError::InvalidValue { offset, expected: "a char" })
+    }
+}
+
+impl Encode for () {
+    fn encode(&self, _writer: &mut ByteWriter) {}
+}
+
+impl Decode for () {
+    fn decode<T: AsRef<[u8]>>(_reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        Ok(())
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, writer: &mut ByteWriter) {
+        writer.write_prefixed_string(LengthPrefix::Varint, self);
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, writer: &mut ByteWriter) {
+        self.as_str().encode(writer);
+    }
+}
+
+impl Decode for String {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        reader.try_read_prefixed_string(LengthPrefix::Varint, reader.remaining())
+    }
+}
+
+/// A collection length, which can't exceed the bytes left. That bounds both
+/// the allocation and the work for items that encode to nothing.
+fn decode_len<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<usize, ReadError> {
+    let offset = reader.offset();
+    let len = usize::decode(reader)?;
+    match reader.remaining() {
+        max if len > max => Err(ReadError::TooLong { offset, len: len as u64, max }),
+        _ => Ok(len),
+    }
+}
+
+impl<E: Encode> Encode for [E] {
+    fn encode(&self, writer: &mut ByteWriter) {
+        writer.write_varint(self.len() as u64);
+        for item in self {
+            item.encode(writer);
+        }
+    }
+}
+
+impl<E: Encode> Encode for Vec<E> {
+    fn encode(&self, writer: &mut ByteWriter) {
+        self.as_slice().encode(writer);
+    }
+}
+
+impl<D: Decode> Decode for Vec<D> {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        let len = decode_len(reader)?;
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(D::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<E: Encode, const N: usize> Encode for [E; N] {
+    fn encode(&self, writer: &mut ByteWriter) {
+        for item in self {
+            item.encode(writer);
+        }
+    }
+}
+
+impl<D: Decode, const N: usize> Decode for [D; N] {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(D::decode(reader)?);
+        }
+        Ok(items.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+impl<E: Encode> Encode for Option<E> {
+    fn encode(&self, writer: &mut ByteWriter) {
+        match self {
+            None => writer.write_u8(0),
+            Some(value) => {
+                writer.write_u8(1);
+                value.encode(writer);
+            }
+        }
+    }
+}
+
+impl<D: Decode> Decode for Option<D> {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        match reader.peek_byte()? {
+            0 => reader.try_skip_bytes(1).map(|_| None),
+            1 => {
+                reader.try_skip_bytes(1)?;
+                D::decode(reader).map(Some)
+            }
+            _ => Err(reader.invalid("an Option tag")),
+        }
+    }
+}
+
+impl<E: Encode + ?Sized> Encode for &E {
+    fn encode(&self, writer: &mut ByteWriter) {
+        (**self).encode(writer);
+    }
+}
+
+impl<E: Encode + ?Sized> Encode for Box<E> {
+    fn encode(&self, writer: &mut ByteWriter) {
+        (**self).encode(writer);
+    }
+}
+
+impl<D: Decode> Decode for Box<D> {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        reader.nested(|reader| D::decode(reader).map(Box::new))
+    }
+}
+
+impl<K: Encode, V: Encode, S> Encode for HashMap<K, V, S> {
+    fn encode(&self, writer: &mut ByteWriter) {
+        writer.write_varint(self.len() as u64);
+        for (key, value) in self {
+            key.encode(writer);
+            value.encode(writer);
+        }
+    }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode> Decode for HashMap<K, V> {
+    fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+        let len = decode_len(reader)?;
+        let mut map = HashMap::with_capacity(len);
+        for _ in 0..len {
+            map.insert(K::decode(reader)?, V::decode(reader)?);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! tuples {
+    ($(($($name:ident)+))*) => {$(
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, writer: &mut ByteWriter) {
+                let ($($name,)+) = self;
+                $($name.encode(writer);)+
+            }
+        }
+
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+                Ok(($($name::decode(reader)?,)+))
+            }
+        }
+    )*};
+}
+
+tuples! {
+    (A)
+    (A B)
+    (A B C)
+    (A B C D)
+    (A B C D E)
+    (A B C D E F)
+    (A B C D E F G)
+    (A B C D E F G H)
+}
+
+/// Structs whose fields are all encoded in order.
+macro_rules! structs {
+    ($($ty:ty { $($field:ident),+ })*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, writer: &mut ByteWriter) {
+                $(self.$field.encode(writer);)+
+            }
+        }
+
+        impl Decode for $ty {
+            fn decode<T: AsRef<[u8]>>(reader: &mut ByteReader<T>) -> Result<Self, ReadError> {
+                Ok(Self { $($field: Decode::decode(reader)?),+ })
+            }
+        }
+    )*};
+}
+
+structs! {
+    Vec2 { x, y }
+    Vec3 { x, y, z }
+    Vec4 { x, y, z, w }
+}
+
+#[cfg(feature = "graphics")]
+structs! {
+    crate::graphics::formats::RGBA { r, g, b, a }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let value = (
+            (7u8, -2i16, 3.5f64, usize::MAX, -5isize),
+            (true, 'ß', String::from("héllo"), vec![Some(1u32), None]),
+            ([Vec2::new(1.0, 2.0); 2], Vec3::new(1.0, 2.0, 3.0), Vec4::new(1.0, 2.0, 3.0, 4.0)),
+            HashMap::from([(String::from("a"), vec![1u8, 2, 3]), (String::new(), vec![])]),
+        );
+        let bytes = value.to_bytes();
+        let decoded = <(
+            (u8, i16, f64, usize, isize),
+            (bool, char, String, Vec<Option<u32>>),
+            ([Vec2; 2], Vec3, Vec4),
+            HashMap<String, Vec<u8>>,
+        )>::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.0, value.0);
+        assert_eq!(decoded.1, value.1);
+        assert_eq!(decoded.2.0.map(|v| (v.x, v.y)), [(1.0, 2.0); 2]);
+        assert_eq!((decoded.2.1.z, decoded.2.2.w), (3.0, 4.0));
+        assert_eq!(decoded.3, value.3);
+        // Fixed-size numbers are little-endian, lengths are varints.
+        assert_eq!(bytes[..4], [7, 0xFE, 0xFF, 0]);
+        assert_eq!(vec![1u16; 200].to_bytes()[..3], [0xC8, 0x01, 1]);
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        assert_eq!(bool::from_bytes(&[2]), Err(ReadError::InvalidValue { offset: 0, expected: "a bool" }));
+        assert_eq!(
+            Option::<u8>::from_bytes(&[1, 5, 0]),
+            Err(ReadError::InvalidValue { offset: 2, expected: "end of input" }),
+        );
+        assert_eq!(char::from_bytes(&0xD800u32.to_le_bytes()), Err(ReadError::InvalidValue { offset: 0, expected: "a char" }));
+        // Huge lengths are refused before allocating or looping.
+        let mut bytes = ByteWriter::new();
+        bytes.write_varint(u32::MAX as u64);
+        bytes.write_u8(1);
+        let bytes = bytes.finish();
+        assert_eq!(Vec::<u64>::from_bytes(&bytes), Err(ReadError::TooLong { offset: 0, len: u32::MAX as u64, max: 1 }));
+        assert_eq!(Vec::<()>::from_bytes(&bytes), Err(ReadError::TooLong { offset: 0, len: u32::MAX as u64, max: 1 }));
+        assert!(String::from_bytes(&bytes).is_err());
+        let mut bytes = ByteWriter::new();
+        bytes.write_varint(u64::MAX);
+        assert_eq!(String::from_bytes(&bytes.finish()), Err(ReadError::TooLong { offset: 0, len: u64::MAX, max: 10 }));
+        assert_eq!(String::from_bytes(&[3, b'a', b'b']), Err(ReadError::UnexpectedEof { offset: 0, needed: 4, available: 3 }));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derived_impls_round_trip() {
+        #[derive(Debug, PartialEq, crate::io::Encode, crate::io::Decode)]
+        struct Player<T> {
+            name: String,
+            score: T,
+            last_move: Option<Move>,
+        }
+
+        #[derive(Debug, PartialEq, crate::io::Encode, crate::io::Decode)]
+        enum Move {
+            Pass,
+            Step(i8, i8),
+            Jump { height: u16 },
+        }
+
+        #[derive(Debug, PartialEq, crate::io::Encode, crate::io::Decode)]
+        struct Unit;
+
+        let players = vec![
+            Player { name: "a".into(), score: 3u64, last_move: Some(Move::Step(-1, 1)) },
+            Player { name: "b".into(), score: 0, last_move: Some(Move::Jump { height: 2 }) },
+            Player { name: "c".into(), score: 9, last_move: Some(Move::Pass) },
+        ];
+        assert_eq!(Vec::<Player<u64>>::from_bytes(&players.to_bytes()), Ok(players));
+        assert_eq!(Unit::from_bytes(&Unit.to_bytes()), Ok(Unit));
+        assert_eq!(Move::Jump { height: 2 }.to_bytes(), [2, 2, 0]);
+        assert_eq!(Move::from_bytes(&[3]), Err(ReadError::InvalidValue { offset: 0, expected: "a Move variant" }));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn bounds_the_nesting_of_recursive_types() {
+        use crate::io::MAX_DEPTH;
+
+        #[derive(Debug, PartialEq, crate::io::Encode, crate::io::Decode)]
+        enum List {
+            Nil,
+            Cons(Box<List>),
+        }
+
+        // The list and the box each count as a level.
+        let mut list = List::Nil;
+        for _ in 0..MAX_DEPTH / 2 - 1 {
+            list = List::Cons(Box::new(list));
+        }
+        let bytes = list.to_bytes();
+        assert_eq!(List::from_bytes(&bytes), Ok(list));
+
+        let mut hostile = vec![1; 1 << 20];
+        hostile.push(0);
+        let depth = MAX_DEPTH / 2;
+        let expected = ReadError::InvalidValue { offset: depth, expected: "at most 256 levels of nesting" };
+        assert_eq!(List::from_bytes(&hostile), Err(expected));
+    }
+}
@@ -0,0 +1,50 @@
+/// Byte order of a multi-byte number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Self = Self::Big;
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Self = Self::Little;
+}
+
+/// A fixed-size number that [`ByteReader`](super::ByteReader) and
+/// [`ByteWriter`](super::ByteWriter) can read and write in either byte order.
+pub trait Number: Copy {
+    const SIZE: usize;
+
+    /// `bytes` must be exactly [`Number::SIZE`] long.
+    fn from_bytes(bytes: &[u8], endian: Endian) -> Self;
+
+    fn write_bytes(self, out: &mut Vec<u8>, endian: Endian);
+}
+
+macro_rules! numbers {
+    ($($ty:ty)*) => {$(
+        impl Number for $ty {
+            const SIZE: usize = size_of::<$ty>();
+
+            fn from_bytes(bytes: &[u8], endian: Endian) -> Self {
+                let mut array = [0; size_of::<$ty>()];
+                array.copy_from_slice(bytes);
+                match endian {
+                    Endian::Big => <$ty>::from_be_bytes(array),
+                    Endian::Little => <$ty>::from_le_bytes(array),
+                }
+            }
+
+            fn write_bytes(self, out: &mut Vec<u8>, endian: Endian) {
+                match endian {
+                    Endian::Big => out.extend_from_slice(&self.to_be_bytes()),
+                    Endian::Little => out.extend_from_slice(&self.to_le_bytes()),
+                }
+            }
+        }
+    )*};
+}
+
+numbers!(u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64);
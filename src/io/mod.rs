@@ -1,3 +1,19 @@
+mod endian;
+mod varint;
 mod byte_reader;
+mod bit_reader;
+mod codec;
+pub use bit_reader::BitOrder;
+pub use bit_reader::BitReader;
+pub use bit_reader::BitWriter;
 pub use byte_reader::ByteReader;
-pub use byte_reader::ByteWriter;
\ No newline at end of file
+pub use byte_reader::ByteWriter;
+pub use byte_reader::ReadError;
+pub use byte_reader::MAX_DEPTH;
+pub use codec::Decode;
+pub use codec::Encode;
+#[cfg(feature = "derive")]
+pub use iron_oxide_derive::{Decode, Encode};
+pub use endian::Endian;
+pub use endian::Number;
+pub use varint::LengthPrefix;
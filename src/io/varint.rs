@@ -0,0 +1,47 @@
+use super::Endian;
+
+/// Longest LEB128 encoding of a `u64`.
+pub(super) const MAX_VARINT_LEN: usize = 10;
+
+/// How the length in front of a prefixed string or blob is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    U16(Endian),
+    U32(Endian),
+    /// An unsigned LEB128 varint.
+    Varint,
+}
+
+impl LengthPrefix {
+    /// The largest length the prefix can hold.
+    pub const fn max_len(self) -> u64 {
+        match self {
+            Self::U16(_) => u16::MAX as u64,
+            Self::U32(_) => u32::MAX as u64,
+            Self::Varint => u64::MAX,
+        }
+    }
+}
+
+/// Maps signed to unsigned so small magnitudes stay short: 0, -1, 1, -2, ...
+/// become 0, 1, 2, 3, ...
+pub(super) const fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub(super) const fn zigzag_decode(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zigzag_interleaves_signs() {
+        for (signed, unsigned) in [(0, 0), (-1, 1), (1, 2), (-2, 3), (i64::MAX, u64::MAX - 1), (i64::MIN, u64::MAX)] {
+            assert_eq!(zigzag_encode(signed), unsigned);
+            assert_eq!(zigzag_decode(unsigned), signed);
+        }
+    }
+}
@@ -1,3 +1,6 @@
+// Lets `#[derive(Encode, Decode)]` name `::iron_oxide` inside this crate too.
+extern crate self as iron_oxide;
+
 pub mod net;
 pub mod io;
 pub mod rand;
@@ -0,0 +1,157 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+use super::{HTTPRequest, ParseError};
+
+/// Longest chunk-size or trailer line accepted while streaming.
+const MAX_LINE_LEN: u64 = 1024;
+
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    /// Bytes left of a `Content-Length` body.
+    Length(u64),
+    /// Bytes left of the current chunk, 0 between chunks.
+    Chunked(u64),
+    Done,
+}
+
+/// Reads a request body straight from the connection, undoing chunked
+/// transfer encoding, so large uploads don't have to fit in memory.
+pub(crate) struct BodyReader<R> {
+    inner: BufReader<R>,
+    framing: Framing,
+    /// Body bytes that may still be announced by chunk sizes.
+    allowed: u64,
+}
+
+impl<R: Read> BodyReader<R> {
+    /// `inner` must start at the first byte after the head of `request`.
+    ///
+    /// A body longer than `max_len` is refused as soon as its
+    /// `Content-Length` or a chunk size gives it away, the latter with an
+    /// [`io::ErrorKind::FileTooLarge`] error.
+    pub(crate) fn new(request: &HTTPRequest, inner: R, max_len: usize) -> Result<Self, ParseError> {
+        let framing = if request.headers.has_token("Transfer-Encoding", "chunked") {
+            Framing::Chunked(0)
+        } else {
+            match request.content_length()? {
+                Some(len) if len > max_len => return Err(ParseError::BodyTooLarge),
+                Some(0) | None => Framing::Done,
+                Some(len) => Framing::Length(len as u64),
+            }
+        };
+        Ok(Self { inner: BufReader::new(inner), framing, allowed: max_len as u64 })
+    }
+
+    /// A chunked body, as in a response.
+    pub(crate) fn chunked(inner: R, max_len: usize) -> Self {
+        Self { inner: BufReader::new(inner), framing: Framing::Chunked(0), allowed: max_len as u64 }
+    }
+
+    /// The bytes read ahead past the body, and the reader they came from.
+    pub(crate) fn into_parts(self) -> (Vec<u8>, R) {
+        let ahead = self.inner.buffer().to_vec();
+        (ahead, self.inner.into_inner())
+    }
+
+    fn line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        (&mut self.inner).take(MAX_LINE_LEN).read_until(b'\n', &mut line)?;
+        if line.last() != Some(&b'\n') {
+            return Err(invalid("chunk line too long or cut off"));
+        }
+        String::from_utf8(line).map_err(|_| invalid("chunk line is not UTF-8"))
+    }
+
+    /// Reads the next chunk-size line, and the trailer fields after the last chunk.
+    fn next_chunk(&mut self) -> io::Result<Framing> {
+        let line = self.line()?;
+        let size = line.trim_end().split(';').next().unwrap_or("").trim();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid("malformed chunk size"));
+        }
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid("chunk size too large"))?;
+        if size > self.allowed {
+            return Err(io::Error::new(io::ErrorKind::FileTooLarge, "body too large"));
+        }
+        if size > 0 {
+            self.allowed -= size;
+            return Ok(Framing::Chunked(size));
+        }
+        for _ in 0..100 {
+            if self.line()?.trim_end().is_empty() {
+                return Ok(Framing::Done);
+            }
+        }
+        Err(invalid("too many trailer fields"))
+    }
+}
+
+impl<R: Read> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = loop {
+            match self.framing {
+                Framing::Done => return Ok(0),
+                Framing::Chunked(0) => self.framing = self.next_chunk()?,
+                Framing::Length(left) | Framing::Chunked(left) => break left,
+            }
+        };
+
+        let len = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 && len > 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed inside the body"));
+        }
+        let left = left - n as u64;
+        self.framing = match self.framing {
+            Framing::Length(_) if left == 0 => Framing::Done,
+            Framing::Length(_) => Framing::Length(left),
+            Framing::Chunked(_) if left == 0 => {
+                // The line break after the chunk.
+                if !self.line()?.trim_end().is_empty() {
+                    return Err(invalid("chunk longer than its size"));
+                }
+                Framing::Chunked(0)
+            }
+            Framing::Chunked(_) => Framing::Chunked(left),
+            Framing::Done => Framing::Done,
+        };
+        Ok(n)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_length_and_chunked_bodies() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n";
+        let (request, _) = HTTPRequest::parse_head(raw, 1024).unwrap().unwrap();
+        let mut body = String::new();
+        let mut reader = BodyReader::new(&request, &b"helloNEXT"[..], 5).unwrap();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+        let (ahead, rest) = reader.into_parts();
+        assert_eq!([&ahead[..], rest].concat(), b"NEXT");
+        assert!(matches!(BodyReader::new(&request, &b""[..], 4), Err(ParseError::BodyTooLarge)));
+
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let (request, _) = HTTPRequest::parse_head(raw, 1024).unwrap().unwrap();
+        let chunked = &b"4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nTrailer: x\r\n\r\nNEXT"[..];
+        let mut body = String::new();
+        BodyReader::new(&request, chunked, 9).unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "Wikipedia");
+        let error = BodyReader::new(&request, chunked, 8).unwrap().read_to_string(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::FileTooLarge);
+
+        let mut body = Vec::new();
+        let error = BodyReader::new(&request, &b"3\r\nabcdef\r\n0\r\n\r\n"[..], 100).unwrap().read_to_end(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = BodyReader::new(&request, &b"+3\r\nabc\r\n0\r\n\r\n"[..], 100).unwrap().read_to_end(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Mutex,
+    time::Duration,
+};
+
+use super::{body_reader::BodyReader, Body, Error, HTTPRequest, Headers, Method, ParseError, Response, Result, Url};
+
+/// Blocking HTTP/1.1 client that keeps idle connections per host for reuse.
+pub struct HttpClient {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    max_idle_per_host: usize,
+    max_body_size: usize,
+    headers: Headers,
+    pool: Mutex<HashMap<(String, u16), Vec<TcpStream>>>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        let mut headers = Headers::new();
+        headers.set("User-Agent", concat!("iron_oxide/", env!("CARGO_PKG_VERSION")));
+        Self {
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+            max_redirects: 10,
+            max_idle_per_host: 4,
+            max_body_size: 64 * 1024 * 1024,
+            headers,
+            pool: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Read and write timeout for every socket operation.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Redirects to follow before giving up; 0 returns redirects as they are.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Idle connections kept per host; 0 disables reuse.
+    pub fn max_idle_per_host(mut self, max_idle: usize) -> Self {
+        self.max_idle_per_host = max_idle;
+        self
+    }
+
+    /// Largest response body, 64 MiB by default. Longer ones fail with
+    /// [`ParseError::BodyTooLarge`] instead of being buffered.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Header sent with every request.
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder<'_> {
+        RequestBuilder { client: self, method, url: url.to_string(), headers: self.headers.clone(), body: Vec::new() }
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response> {
+        self.request(Method::GET, url).send()
+    }
+
+    pub fn post(&self, url: &str, content_type: &str, body: impl Into<Vec<u8>>) -> Result<Response> {
+        self.request(Method::POST, url).header("Content-Type", content_type).body(body).send()
+    }
+
+    fn execute(&self, mut method: Method, url: &str, headers: Headers, mut body: Vec<u8>) -> Result<Response> {
+        let mut url = Url::parse(url)?;
+        let mut headers = headers;
+        let mut redirects = 0;
+
+        loop {
+            let response = self.send_once(method, &url, &headers, &body)?;
+            let location = match response.status {
+                301 | 302 | 303 | 307 | 308 if self.max_redirects > 0 => response.headers.get("Location"),
+                _ => None,
+            };
+            let Some(location) = location else {
+                return Ok(response);
+            };
+            if redirects == self.max_redirects {
+                return Err(Error::TooManyRedirects(redirects));
+            }
+
+            let next = url.join(location)?;
+            // Credentials are only meant for the origin they were given to.
+            if (&next.scheme, &next.host, next.port) != (&url.scheme, &url.host, url.port) {
+                for name in ["Authorization", "Cookie", "Proxy-Authorization"] {
+                    headers.remove(name);
+                }
+            }
+            url = next;
+            // 307 and 308 repeat the request as is, the others switch to GET.
+            if response.status == 303 || (matches!(response.status, 301 | 302) && method == Method::POST) {
+                if method != Method::HEAD {
+                    method = Method::GET;
+                }
+                body.clear();
+                headers.remove("Content-Type");
+                headers.remove("Content-Length");
+            }
+            redirects += 1;
+        }
+    }
+
+    fn send_once(&self, method: Method, url: &Url, headers: &Headers, body: &[u8]) -> Result<Response> {
+        if url.scheme != "http" {
+            return Err(Error::UnsupportedScheme(url.scheme.clone()));
+        }
+
+        let mut request = HTTPRequest::new(method, &url.target);
+        request.host = Some(url.host_header());
+        request.headers = headers.clone();
+        request.body = body.to_vec();
+
+        let key = (url.host.clone(), url.port);
+        if let Some(stream) = self.take_idle(&key) {
+            match self.exchange(stream, &key, &request) {
+                // The server may have closed the idle connection in the meantime.
+                Err(Error::Io(e)) if is_stale(&e) => (),
+                result => return result,
+            }
+        }
+        let stream = self.connect(url)?;
+        self.exchange(stream, &key, &request)
+    }
+
+    fn connect(&self, url: &Url) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host did not resolve");
+        for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+            let stream = match self.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.timeout)?;
+                    stream.set_write_timeout(self.timeout)?;
+                    let _ = stream.set_nodelay(true);
+                    return Ok(stream);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn take_idle(&self, key: &(String, u16)) -> Option<TcpStream> {
+        self.pool.lock().ok()?.get_mut(key)?.pop()
+    }
+
+    fn put_idle(&self, key: &(String, u16), stream: TcpStream) {
+        if let Ok(mut pool) = self.pool.lock() {
+            let idle = pool.entry(key.clone()).or_default();
+            if idle.len() < self.max_idle_per_host {
+                idle.push(stream);
+            }
+        }
+    }
+
+    fn exchange(&self, mut stream: TcpStream, key: &(String, u16), request: &HTTPRequest) -> Result<Response> {
+        request.write_to(&mut stream)?;
+        stream.flush()?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        let (mut response, body_start) = loop {
+            if let Some((head, body_start)) = Response::parse_head(&buffer)? {
+                // Interim answers like `100 Continue` come before the real one.
+                if (100..200).contains(&head.status) && head.status != 101 {
+                    buffer.drain(..body_start);
+                    continue;
+                }
+                break (head, body_start);
+            }
+            if read_more(&mut stream, &mut buffer)? == 0 {
+                let kind = if buffer.is_empty() { io::ErrorKind::ConnectionAborted } else { io::ErrorKind::UnexpectedEof };
+                return Err(io::Error::new(kind, "connection closed before the response head").into());
+            }
+        };
+        buffer.drain(..body_start);
+
+        let mut reusable = !response.headers.has_token("Connection", "close");
+        let body = if request.method == Method::HEAD || response.is_bodyless() {
+            Vec::new()
+        } else if response.headers.has_token("Transfer-Encoding", "chunked") {
+            let inner = io::Cursor::new(std::mem::take(&mut buffer)).chain(&mut stream);
+            let mut reader = BodyReader::chunked(inner, self.max_body_size);
+            let mut body = Vec::new();
+            reader.read_to_end(&mut body).map_err(|e| match e.kind() {
+                io::ErrorKind::FileTooLarge => Error::Parse(ParseError::BodyTooLarge),
+                _ => Error::Io(e),
+            })?;
+            let (ahead, inner) = reader.into_parts();
+            let (rest, _) = inner.into_inner();
+            reusable &= ahead.is_empty() && rest.position() == rest.get_ref().len() as u64;
+            body
+        } else if let Some(len) = response.headers.content_length() {
+            if len > self.max_body_size as u64 {
+                return Err(ParseError::BodyTooLarge.into());
+            }
+            let len = len as usize;
+            while buffer.len() < len {
+                if read_more(&mut stream, &mut buffer)? == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body shorter than Content-Length").into());
+                }
+            }
+            reusable &= buffer.len() == len;
+            buffer.truncate(len);
+            buffer
+        } else {
+            // Without framing the body ends when the server closes.
+            let limit = self.max_body_size.saturating_sub(buffer.len()) as u64 + 1;
+            (&mut stream).take(limit).read_to_end(&mut buffer)?;
+            if buffer.len() > self.max_body_size {
+                return Err(ParseError::BodyTooLarge.into());
+            }
+            reusable = false;
+            buffer
+        };
+
+        if reusable && self.max_idle_per_host > 0 {
+            self.put_idle(key, stream);
+        }
+        response.body = Body::Bytes(body);
+        Ok(response)
+    }
+}
+
+fn read_more(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0; 8192];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(n) => {
+                buffer.extend_from_slice(&chunk[..n]);
+                return Ok(n);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn is_stale(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe)
+}
+
+/// A request being assembled by [`HttpClient::request`].
+pub struct RequestBuilder<'a> {
+    client: &'a HttpClient,
+    method: Method,
+    url: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl RequestBuilder<'_> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn send(self) -> Result<Response> {
+        self.client.execute(self.method, &self.url, self.headers, self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{Router, Server};
+
+    #[test]
+    fn talks_to_own_server() {
+        let mut router = Router::new();
+        router.get("/hello", |_, _| Response::text("hello"));
+        router.get("/moved", |_, _| Response::redirect(302, "hello"));
+        router.get("/chunked", |_, _| Response::ok().body(Body::reader(&b"streamed body"[..], None)));
+        router.post("/echo", |request, _| Response::ok().body(request.body.clone()));
+        let server = Server::bind("127.0.0.1:0", router).unwrap().workers(2).spawn().unwrap();
+        let base = format!("http://{}", server.local_addr());
+
+        let client = HttpClient::new();
+        let response = client.get(&format!("{base}/hello")).unwrap();
+        assert_eq!(response.body.as_bytes(), Some(&b"hello"[..]));
+        assert_eq!(client.pool.lock().unwrap().values().map(Vec::len).sum::<usize>(), 1);
+
+        let response = client.get(&format!("{base}/moved")).unwrap();
+        assert_eq!((response.status, response.body.as_bytes()), (200, Some(&b"hello"[..])));
+        let response = client.get(&format!("{base}/chunked")).unwrap();
+        assert_eq!(response.body.as_bytes(), Some(&b"streamed body"[..]));
+        let response = client.post(&format!("{base}/echo"), "text/plain", "ping").unwrap();
+        assert_eq!(response.body.as_bytes(), Some(&b"ping"[..]));
+
+        let response = HttpClient::new().max_redirects(0).get(&format!("{base}/moved")).unwrap();
+        assert_eq!(response.status, 302);
+
+        drop(client);
+        server.shutdown();
+    }
+
+    #[test]
+    fn drops_credentials_on_cross_origin_redirects() {
+        let mut router = Router::new();
+        router.get("/echo", |request, _| {
+            let names = ["Authorization", "Cookie", "Proxy-Authorization", "X-Trace"];
+            let sent: Vec<_> = names.iter().filter(|name| request.headers.contains(name)).copied().collect();
+            Response::text(sent.join(","))
+        });
+        let other = Server::bind("127.0.0.1:0", router).unwrap().spawn().unwrap();
+        let target = format!("http://{}/echo", other.local_addr());
+
+        let mut router = Router::new();
+        router.get("/away", move |_, _| Response::redirect(302, &target));
+        router.get("/here", |_, _| Response::redirect(307, "/echo"));
+        router.get("/echo", |request, _| Response::text(request.headers.get("Authorization").unwrap_or("")));
+        let server = Server::bind("127.0.0.1:0", router).unwrap().spawn().unwrap();
+        let base = format!("http://{}", server.local_addr());
+
+        let client = HttpClient::new();
+        let send = |path: &str| {
+            let response = client
+                .request(Method::GET, &format!("{base}{path}"))
+                .header("Authorization", "Bearer secret")
+                .header("Cookie", "session=1")
+                .header("Proxy-Authorization", "Basic eDp5")
+                .header("X-Trace", "1")
+                .send()
+                .unwrap();
+            String::from_utf8(response.body.as_bytes().unwrap().to_vec()).unwrap()
+        };
+        assert_eq!(send("/away"), "X-Trace");
+        assert_eq!(send("/here"), "Bearer secret");
+
+        drop(client);
+        server.shutdown();
+        other.shutdown();
+    }
+
+    /// Answers every connection with `response`, ignoring the request.
+    fn raw_server(response: &'static [u8]) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(response);
+            }
+        });
+        format!("http://{addr}/")
+    }
+
+    #[test]
+    fn skips_interim_responses_and_limits_bodies() {
+        let url = raw_server(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let response = HttpClient::new().get(&url).unwrap();
+        assert_eq!((response.status, response.body.as_bytes()), (200, Some(&b"ok"[..])));
+
+        let client = HttpClient::new().max_body_size(10);
+        let url = raw_server(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n");
+        assert!(matches!(client.get(&url), Err(Error::Parse(ParseError::BodyTooLarge))));
+        let url = raw_server(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n8\r\n12345678\r\n8\r\n");
+        assert!(matches!(client.get(&url), Err(Error::Parse(ParseError::BodyTooLarge))));
+        let url = raw_server(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n0123456789abc");
+        assert!(matches!(client.get(&url), Err(Error::Parse(ParseError::BodyTooLarge))));
+        let url = raw_server(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n12345\r\n5\r\n67890\r\n0\r\n\r\n");
+        assert_eq!(client.get(&url).unwrap().body.as_bytes(), Some(&b"1234567890"[..]));
+    }
+
+    #[test]
+    fn refuses_endless_response_heads() {
+        let mut buffer = b"HTTP/1.1 200 OK\r\n".to_vec();
+        while buffer.len() <= crate::net::http_request::MAX_HEAD_LEN {
+            buffer.extend_from_slice(b"X-Filler: aaaaaaaaaaaaaaaaaaaaaaaa\r\n");
+        }
+        assert!(matches!(Response::parse_head(&buffer), Err(ParseError::TooLarge)));
+        buffer.extend_from_slice(b"\r\n");
+        assert!(matches!(Response::parse_head(&buffer), Err(ParseError::TooLarge)));
+    }
+}
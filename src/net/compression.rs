@@ -0,0 +1,230 @@
+use std::io::{self, Write};
+
+use flate2::{write::{GzEncoder, ZlibEncoder}, Compression};
+
+use super::{mime, Body, HTTPRequest, Response};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`.
+    Deflate,
+    Identity,
+}
+
+impl ContentEncoding {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Identity => "identity",
+        }
+    }
+
+    /// Picks the best supported coding from an `Accept-Encoding` value.
+    /// Equal weights prefer gzip over deflate.
+    pub fn negotiate(accept_encoding: &str) -> Self {
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut wildcard = None;
+
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match coding.as_str() {
+                "gzip" | "x-gzip" => gzip = Some(quality),
+                "deflate" => deflate = Some(quality),
+                "*" => wildcard = Some(quality),
+                _ => (),
+            }
+        }
+
+        let gzip = gzip.or(wildcard).unwrap_or(0.0);
+        let deflate = deflate.or(wildcard).unwrap_or(0.0);
+        if gzip > 0.0 && gzip >= deflate {
+            Self::Gzip
+        } else if deflate > 0.0 {
+            Self::Deflate
+        } else {
+            Self::Identity
+        }
+    }
+}
+
+/// When and how responses get a `Content-Encoding`.
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionConfig {
+    /// Bodies smaller than this are sent as they are.
+    pub min_size: u64,
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self { min_size: 1024, level: 6 }
+    }
+}
+
+impl CompressionConfig {
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+        self
+    }
+}
+
+/// Text-like types benefit from compression; media formats already are compressed.
+pub fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    mime::is_text_mime(&essence) || matches!(essence.as_str(), "image/svg+xml" | "application/wasm" | "image/bmp")
+}
+
+impl Response {
+    /// Compresses the body if the client accepts gzip or deflate, the content
+    /// type is text-like and the body is at least `config.min_size` bytes.
+    pub fn compress(mut self, request: &HTTPRequest, config: &CompressionConfig) -> Self {
+        let compressible = self.headers.get("Content-Type").is_some_and(is_compressible);
+        if !compressible || self.is_bodyless() || self.status == 206 || self.headers.contains("Content-Encoding") {
+            return self;
+        }
+        if !self.headers.has_token("Vary", "Accept-Encoding") {
+            self.headers.append("Vary", "Accept-Encoding");
+        }
+
+        let encoding = request.headers.get("Accept-Encoding").map_or(ContentEncoding::Identity, ContentEncoding::negotiate);
+        if encoding == ContentEncoding::Identity || self.body.len().is_some_and(|len| len < config.min_size) {
+            return self;
+        }
+
+        let level = Compression::new(config.level);
+        self.body = match std::mem::take(&mut self.body) {
+            Body::Bytes(bytes) => match encode(encoding, level, &bytes) {
+                Ok(compressed) => Body::Bytes(compressed),
+                Err(_) => {
+                    self.body = Body::Bytes(bytes);
+                    return self;
+                }
+            },
+            Body::Reader { mut reader, .. } => Body::stream(move |writer| {
+                let mut encoder = Encoder::new(encoding, level, writer);
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()
+            }),
+            Body::Stream(body) => Body::stream(move |writer| {
+                let mut encoder = Encoder::new(encoding, level, writer);
+                body(&mut encoder)?;
+                encoder.finish()
+            }),
+        };
+
+        self.headers.remove("Content-Length");
+        self.headers.set("Content-Encoding", encoding.as_str());
+        // The compressed representation needs its own validator.
+        if let Some(etag) = self.headers.get("ETag").and_then(|etag| encoded_etag(etag, encoding)) {
+            self.headers.set("ETag", etag);
+        }
+        self
+    }
+}
+
+/// The validator [`Response::compress`] gives the `encoding` form of a
+/// response tagged `etag`.
+pub(crate) fn encoded_etag(etag: &str, encoding: ContentEncoding) -> Option<String> {
+    let tag = etag.strip_suffix('"')?;
+    Some(format!("{tag}-{}\"", encoding.as_str()))
+}
+
+fn encode(encoding: ContentEncoding, level: Compression, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(encoding, level, Vec::with_capacity(data.len() / 2));
+    encoder.write_all(data)?;
+    match encoder {
+        Encoder::Gzip(e) => e.finish(),
+        Encoder::Deflate(e) => e.finish(),
+        Encoder::Identity(w) => Ok(w),
+    }
+}
+
+enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>),
+    Identity(W),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(encoding: ContentEncoding, level: Compression, writer: W) -> Self {
+        match encoding {
+            ContentEncoding::Gzip => Self::Gzip(GzEncoder::new(writer, level)),
+            ContentEncoding::Deflate => Self::Deflate(ZlibEncoder::new(writer, level)),
+            ContentEncoding::Identity => Self::Identity(writer),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Gzip(e) => e.finish().map(|_| ()),
+            Self::Deflate(e) => e.finish().map(|_| ()),
+            Self::Identity(_) => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(e) => e.write(buf),
+            Self::Deflate(e) => e.write(buf),
+            Self::Identity(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Gzip(e) => e.flush(),
+            Self::Deflate(e) => e.flush(),
+            Self::Identity(w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(ContentEncoding::negotiate("gzip, deflate, br"), ContentEncoding::Gzip);
+        assert_eq!(ContentEncoding::negotiate("gzip;q=0.5, deflate"), ContentEncoding::Deflate);
+        assert_eq!(ContentEncoding::negotiate("gzip;q=0, *;q=0.1"), ContentEncoding::Deflate);
+        assert_eq!(ContentEncoding::negotiate("br"), ContentEncoding::Identity);
+    }
+
+    #[test]
+    fn compresses_text_bodies_above_threshold() {
+        let request = HTTPRequest::parse(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+        let config = CompressionConfig::default();
+        let text = "hello ".repeat(500);
+
+        let response = Response::text(text.clone()).header("ETag", "\"abc\"").compress(&request, &config);
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("\"abc-gzip\""));
+        let mut decoded = String::new();
+        GzDecoder::new(response.body.as_bytes().unwrap()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+
+        let small = Response::text("tiny").compress(&request, &config);
+        assert!(!small.headers.contains("Content-Encoding"));
+        let png = Response::ok().content_type("image/png").body(vec![0; 4096]).compress(&request, &config);
+        assert!(!png.headers.contains("Content-Encoding"));
+    }
+}
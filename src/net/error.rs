@@ -0,0 +1,72 @@
+use std::{fmt, io};
+
+use super::{FrameError, ParseError};
+
+/// Errors returned by the networking types in this module.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(ParseError),
+    Frame(FrameError),
+    /// The WebSocket opening handshake was refused or malformed.
+    Handshake(String),
+    UnsupportedScheme(String),
+    TooManyRedirects(usize),
+    /// The connection or the thread serving it is gone.
+    Closed,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Parse(e) => write!(f, "parse error: {e}"),
+            Self::Frame(e) => write!(f, "invalid frame: {e}"),
+            Self::Handshake(reason) => write!(f, "WebSocket handshake failed: {reason}"),
+            Self::UnsupportedScheme(scheme) => write!(f, "unsupported URL scheme `{scheme}`"),
+            Self::TooManyRedirects(n) => write!(f, "gave up after {n} redirects"),
+            Self::Closed => f.write_str("connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Parse(e) => Some(e),
+            Self::Frame(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(e: FrameError) -> Self {
+        Self::Frame(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Closed => io::Error::new(io::ErrorKind::NotConnected, e),
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
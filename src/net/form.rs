@@ -0,0 +1,655 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{parse_query, HTTPRequest};
+
+/// Limits and the upload directory for decoding form bodies.
+#[derive(Debug, Clone)]
+pub struct FormConfig {
+    /// Text fields accepted, across both encodings.
+    pub max_fields: usize,
+    /// Bytes per text field.
+    pub max_field_size: usize,
+    pub max_files: usize,
+    /// Bytes per uploaded file.
+    pub max_file_size: u64,
+    /// Where file parts are written while the body is read.
+    pub upload_dir: PathBuf,
+}
+
+impl Default for FormConfig {
+    fn default() -> Self {
+        Self {
+            max_fields: 1000,
+            max_field_size: 64 * 1024,
+            max_files: 20,
+            max_file_size: 100 << 20,
+            upload_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+impl FormConfig {
+    pub fn max_fields(mut self, max_fields: usize) -> Self {
+        self.max_fields = max_fields;
+        self
+    }
+
+    pub fn max_field_size(mut self, max_field_size: usize) -> Self {
+        self.max_field_size = max_field_size;
+        self
+    }
+
+    pub fn max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn upload_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.upload_dir = dir.into();
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    Io(io::Error),
+    /// The body is neither urlencoded nor `multipart/form-data`.
+    UnsupportedContentType(String),
+    MissingBoundary,
+    /// The multipart framing is broken or the body ends early.
+    Malformed(&'static str),
+    /// Bad percent escape or a text field that is not UTF-8.
+    InvalidEncoding,
+    /// An urlencoded body longer than the server's `max_body_size`.
+    BodyTooLarge,
+    TooManyFields,
+    TooManyFiles,
+    FieldTooLarge(String),
+    FileTooLarge(String),
+    MissingField(String),
+    /// A field that does not parse as the requested type.
+    InvalidField(String),
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::UnsupportedContentType(content_type) => write!(f, "unsupported form content type `{content_type}`"),
+            Self::MissingBoundary => f.write_str("multipart body without boundary"),
+            Self::Malformed(reason) => write!(f, "malformed multipart body: {reason}"),
+            Self::InvalidEncoding => f.write_str("invalid form encoding"),
+            Self::BodyTooLarge => f.write_str("form body too large"),
+            Self::TooManyFields => f.write_str("too many form fields"),
+            Self::TooManyFiles => f.write_str("too many uploaded files"),
+            Self::FieldTooLarge(name) => write!(f, "form field `{name}` too large"),
+            Self::FileTooLarge(name) => write!(f, "uploaded file `{name}` too large"),
+            Self::MissingField(name) => write!(f, "missing form field `{name}`"),
+            Self::InvalidField(name) => write!(f, "invalid value for form field `{name}`"),
+        }
+    }
+}
+
+impl FormError {
+    /// The status to answer a request whose form failed to decode with.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Io(e) if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) => 400,
+            Self::Io(_) => 500,
+            Self::UnsupportedContentType(_) => 415,
+            Self::BodyTooLarge | Self::TooManyFields | Self::TooManyFiles | Self::FieldTooLarge(_) | Self::FileTooLarge(_) => 413,
+            _ => 400,
+        }
+    }
+}
+
+impl std::error::Error for FormError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FormError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A file part written to the upload directory. The file is deleted when
+/// this is dropped unless it was moved away with [`UploadedFile::persist`].
+#[derive(Debug)]
+pub struct UploadedFile {
+    /// Name of the form field.
+    pub name: String,
+    /// File name the client sent, without any directory part.
+    pub file_name: String,
+    pub content_type: Option<String>,
+    pub size: u64,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl UploadedFile {
+    /// Where the upload currently lives.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+
+    /// Moves the upload to `to`, keeping it after this is dropped.
+    pub fn persist(mut self, to: impl AsRef<Path>) -> io::Result<()> {
+        let to = to.as_ref();
+        // A rename fails across file systems, copying does not.
+        if fs::rename(&self.path, to).is_err() {
+            fs::copy(&self.path, to)?;
+            let _ = fs::remove_file(&self.path);
+        }
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Decoded text fields and uploaded files, in the order they were sent.
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl Form {
+    /// First value of the text field `name`.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `name`, e.g. for checkboxes and multi-selects.
+    pub fn field_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields.iter().filter(move |(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Parses the text field `name`, e.g. `form.parse::<u32>("age")`.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        let value = self.field(name).ok_or_else(|| FormError::MissingField(name.to_string()))?;
+        value.trim().parse().map_err(|_| FormError::InvalidField(name.to_string()))
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    /// Removes the first upload for `name`, e.g. to [`UploadedFile::persist`] it.
+    pub fn take_file(&mut self, name: &str) -> Option<UploadedFile> {
+        let index = self.files.iter().position(|file| file.name == name)?;
+        Some(self.files.remove(index))
+    }
+
+    fn push_field(&mut self, name: String, value: String, config: &FormConfig) -> Result<(), FormError> {
+        if self.fields.len() == config.max_fields {
+            return Err(FormError::TooManyFields);
+        }
+        self.fields.push((name, value));
+        Ok(())
+    }
+}
+
+impl HTTPRequest {
+    /// Decodes an `application/x-www-form-urlencoded` or `multipart/form-data`
+    /// body. The body is already in memory here; routes added with
+    /// [`Router::form`](super::Router::form) stream uploads to disk instead.
+    pub fn form(&self, config: &FormConfig) -> Result<Form, FormError> {
+        read_form(self, &self.body[..], config, usize::MAX)
+    }
+}
+
+/// Decodes the form of `request` from `body`. Multipart bodies are streamed,
+/// urlencoded ones are read into memory up to `max_urlencoded` bytes.
+pub(crate) fn read_form(request: &HTTPRequest, body: impl Read, config: &FormConfig, max_urlencoded: usize) -> Result<Form, FormError> {
+    let content_type = request.headers.get("Content-Type").unwrap_or("");
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    if mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        let mut buf = Vec::new();
+        body.take(max_urlencoded.saturating_add(1) as u64).read_to_end(&mut buf)?;
+        if buf.len() > max_urlencoded {
+            return Err(FormError::BodyTooLarge);
+        }
+        parse_urlencoded(&buf, config)
+    } else if mime.eq_ignore_ascii_case("multipart/form-data") {
+        let boundary = content_type_param(content_type, "boundary").ok_or(FormError::MissingBoundary)?;
+        parse_multipart(body, &boundary, config)
+    } else {
+        Err(FormError::UnsupportedContentType(content_type.to_string()))
+    }
+}
+
+/// Decodes an `application/x-www-form-urlencoded` body.
+pub fn parse_urlencoded(body: &[u8], config: &FormConfig) -> Result<Form, FormError> {
+    let body = std::str::from_utf8(body).map_err(|_| FormError::InvalidEncoding)?;
+    let mut form = Form::default();
+    for (name, value) in parse_query(body).map_err(|_| FormError::InvalidEncoding)? {
+        if value.len() > config.max_field_size {
+            return Err(FormError::FieldTooLarge(name));
+        }
+        form.push_field(name, value, config)?;
+    }
+    Ok(form)
+}
+
+/// Value of `name` in a header like `multipart/form-data; boundary="x"`.
+fn content_type_param(header: &str, name: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Longest part head accepted.
+const MAX_PART_HEAD: usize = 8 * 1024;
+
+/// Decodes a `multipart/form-data` body read from `reader`. File parts go to
+/// `config.upload_dir` as they arrive, so only one buffer of the body is held
+/// in memory at a time.
+pub fn parse_multipart(reader: impl Read, boundary: &str, config: &FormConfig) -> Result<Form, FormError> {
+    if boundary.is_empty() || boundary.len() > 70 {
+        return Err(FormError::MissingBoundary);
+    }
+    let mut input = Input { reader, buffer: Vec::with_capacity(16 * 1024), eof: false };
+    // The first delimiter may lack the leading CRLF, every later one has it.
+    let delimiter = format!("\r\n--{boundary}").into_bytes();
+    let first = &delimiter[2..];
+
+    // Skip the preamble.
+    loop {
+        if input.buffer.starts_with(first) {
+            input.buffer.drain(..first.len());
+            break;
+        }
+        if let Some(i) = find(&input.buffer, &delimiter) {
+            input.buffer.drain(..i + delimiter.len());
+            break;
+        }
+        let keep = input.buffer.len().saturating_sub(delimiter.len());
+        input.buffer.drain(..keep);
+        if !input.fill()? {
+            return Err(FormError::Malformed("no boundary"));
+        }
+    }
+
+    let mut form = Form::default();
+    while !input.after_delimiter()? {
+        let head = input.part_head()?;
+        let part = Part::parse(&head)?;
+        match part.file_name {
+            // Browsers send an empty file name when no file was chosen.
+            Some(file_name) if file_name.is_empty() => {
+                input.body(&delimiter, &mut io::sink(), u64::MAX)?;
+            }
+            Some(file_name) => {
+                if form.files.len() == config.max_files {
+                    return Err(FormError::TooManyFiles);
+                }
+                let (path, mut file) = create_upload(&config.upload_dir)?;
+                let mut upload =
+                    UploadedFile { name: part.name, file_name, content_type: part.content_type, size: 0, path, persisted: false };
+                let limit = config.max_file_size;
+                let size = input.body(&delimiter, &mut file, limit)?;
+                if size > limit {
+                    return Err(FormError::FileTooLarge(std::mem::take(&mut upload.name)));
+                }
+                file.flush()?;
+                upload.size = size;
+                form.files.push(upload);
+            }
+            None => {
+                let mut value = Vec::new();
+                let limit = config.max_field_size as u64;
+                if input.body(&delimiter, &mut value, limit)? > limit {
+                    return Err(FormError::FieldTooLarge(part.name));
+                }
+                let value = String::from_utf8(value).map_err(|_| FormError::InvalidEncoding)?;
+                form.push_field(part.name, value, config)?;
+            }
+        }
+    }
+    Ok(form)
+}
+
+/// Buffered reading of a multipart body.
+struct Input<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Input<R> {
+    /// Reads more bytes. Returns `false` at the end of the body.
+    fn fill(&mut self) -> Result<bool, FormError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut chunk = [0; 8192];
+        let n = loop {
+            match self.reader.read(&mut chunk) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        };
+        self.buffer.extend_from_slice(&chunk[..n]);
+        self.eof = n == 0;
+        Ok(n > 0)
+    }
+
+    /// Makes sure `n` bytes are buffered.
+    fn require(&mut self, n: usize) -> Result<(), FormError> {
+        while self.buffer.len() < n {
+            if !self.fill()? {
+                return Err(FormError::Malformed("body ends early"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Consumes what follows a boundary. Returns `true` for the closing one.
+    fn after_delimiter(&mut self) -> Result<bool, FormError> {
+        self.require(2)?;
+        if self.buffer.starts_with(b"--") {
+            return Ok(true);
+        }
+        // Transport padding may precede the line break.
+        loop {
+            if let Some(i) = find(&self.buffer, b"\r\n") {
+                if self.buffer[..i].iter().any(|b| !matches!(b, b' ' | b'\t')) {
+                    return Err(FormError::Malformed("garbage after boundary"));
+                }
+                self.buffer.drain(..i + 2);
+                return Ok(false);
+            }
+            if self.buffer.len() > 256 {
+                return Err(FormError::Malformed("garbage after boundary"));
+            }
+            let len = self.buffer.len();
+            self.require(len + 1)?;
+        }
+    }
+
+    /// Reads the header block of a part, including the blank line.
+    fn part_head(&mut self) -> Result<String, FormError> {
+        loop {
+            // A part without headers starts with the blank line right away.
+            if self.buffer.starts_with(b"\r\n") {
+                self.buffer.drain(..2);
+                return Ok(String::new());
+            }
+            if let Some(i) = find(&self.buffer, b"\r\n\r\n") {
+                let head = String::from_utf8(self.buffer[..i].to_vec()).map_err(|_| FormError::InvalidEncoding)?;
+                self.buffer.drain(..i + 4);
+                return Ok(head);
+            }
+            if self.buffer.len() > MAX_PART_HEAD {
+                return Err(FormError::Malformed("part head too large"));
+            }
+            let len = self.buffer.len();
+            self.require(len + 1)?;
+        }
+    }
+
+    /// Copies a part's content to `out` up to the next delimiter, which is
+    /// consumed. Stops writing once more than `limit` bytes were seen and
+    /// returns the size so far.
+    fn body(&mut self, delimiter: &[u8], out: &mut impl Write, limit: u64) -> Result<u64, FormError> {
+        let mut size = 0;
+        loop {
+            if let Some(i) = find(&self.buffer, delimiter) {
+                size += i as u64;
+                if size <= limit {
+                    out.write_all(&self.buffer[..i])?;
+                }
+                self.buffer.drain(..i + delimiter.len());
+                return Ok(size);
+            }
+            // Everything except a possible start of the delimiter is content.
+            let safe = self.buffer.len().saturating_sub(delimiter.len() - 1);
+            size += safe as u64;
+            if size > limit {
+                return Ok(size);
+            }
+            out.write_all(&self.buffer[..safe])?;
+            self.buffer.drain(..safe);
+            if !self.fill()? {
+                return Err(FormError::Malformed("body ends inside a part"));
+            }
+        }
+    }
+}
+
+/// The headers of one part that matter for forms.
+struct Part {
+    name: String,
+    file_name: Option<String>,
+    content_type: Option<String>,
+}
+
+impl Part {
+    fn parse(head: &str) -> Result<Self, FormError> {
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in head.split("\r\n") {
+            let (name, value) = line.split_once(':').ok_or(FormError::Malformed("bad part header"))?;
+            if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                disposition = Some(value.trim());
+            } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value.trim().to_string());
+            }
+        }
+
+        let disposition = disposition.ok_or(FormError::Malformed("part without Content-Disposition"))?;
+        let name = content_type_param(disposition, "name").ok_or(FormError::Malformed("part without name"))?;
+        // Only the last path segment of the client's file name is kept.
+        let file_name = content_type_param(disposition, "filename")
+            .map(|file_name| file_name.rsplit(['/', '\\']).next().unwrap_or("").to_string());
+        Ok(Self { name, file_name, content_type })
+    }
+}
+
+/// Creates a fresh file in `dir` that no other upload uses.
+fn create_upload(dir: &Path) -> io::Result<(PathBuf, File)> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("iron_oxide_upload_{}_{n}", std::process::id()));
+        match File::options().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out the body a few bytes at a time, so delimiters straddle reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    const BODY: &str = "preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        Grüße\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"age\"\r\n\r\n\
+        42\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"C:\\\\docs\\\\a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line one\r\n--XyX\r\nline two\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\r\n\
+        \r\n--XyZ--\r\nepilogue";
+
+    fn upload_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("iron_oxide_form_{name}_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn decodes_urlencoded_bodies() {
+        let request = HTTPRequest::parse(
+            b"POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 29\r\n\r\nname=Ada+L&tag=a&tag=b%21&n=7",
+        ).unwrap();
+        let form = request.form(&FormConfig::default()).unwrap();
+        assert_eq!(form.field("name"), Some("Ada L"));
+        assert_eq!(form.field_values("tag").collect::<Vec<_>>(), ["a", "b!"]);
+        assert_eq!(form.parse::<u8>("n").unwrap(), 7);
+        assert!(matches!(form.parse::<u8>("missing"), Err(FormError::MissingField(_))));
+
+        let config = FormConfig::default().max_fields(1);
+        assert!(matches!(parse_urlencoded(b"a=1&b=2", &config), Err(FormError::TooManyFields)));
+        assert!(matches!(parse_urlencoded(b"a=%ZZ", &config), Err(FormError::InvalidEncoding)));
+    }
+
+    #[test]
+    fn streams_multipart_files_to_disk() {
+        let dir = upload_dir("ok");
+        let config = FormConfig::default().upload_dir(&dir);
+        let mut form = parse_multipart(Trickle(BODY.as_bytes()), "XyZ", &config).unwrap();
+
+        assert_eq!(form.field("title"), Some("Grüße"));
+        assert_eq!(form.parse::<u32>("age").unwrap(), 42);
+        assert!(matches!(form.parse::<u32>("title"), Err(FormError::InvalidField(_))));
+        assert_eq!(form.files().len(), 1);
+        let file = form.file("upload").unwrap();
+        assert_eq!((file.file_name.as_str(), file.content_type.as_deref()), ("a.txt", Some("text/plain")));
+        assert_eq!(file.read().unwrap(), b"line one\r\n--XyX\r\nline two");
+        assert_eq!(file.size, 25);
+
+        let kept = dir.join("kept.txt");
+        form.take_file("upload").unwrap().persist(&kept).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(fs::read(&kept).unwrap().len(), 25);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn enforces_limits_and_cleans_up() {
+        let dir = upload_dir("limits");
+        let config = FormConfig::default().upload_dir(&dir).max_file_size(10);
+        let result = parse_multipart(Trickle(BODY.as_bytes()), "XyZ", &config);
+        assert!(matches!(result, Err(FormError::FileTooLarge(name)) if name == "upload"));
+
+        let config = FormConfig::default().upload_dir(&dir).max_field_size(3);
+        assert!(matches!(parse_multipart(BODY.as_bytes(), "XyZ", &config), Err(FormError::FieldTooLarge(_))));
+        let truncated = &BODY.as_bytes()[..BODY.len() - 20];
+        assert!(matches!(parse_multipart(truncated, "XyZ", &FormConfig::default().upload_dir(&dir)), Err(FormError::Malformed(_))));
+
+        // Partial uploads are removed again.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn streams_uploads_through_the_server() {
+        use std::net::TcpStream;
+        use super::super::{Method, Response, Router, Server};
+
+        let dir = upload_dir("server");
+        let mut router = Router::new();
+        let small = FormConfig::default().upload_dir(&dir).max_file_size(16 * 1024);
+        let large = FormConfig::default().upload_dir(&dir).max_file_size(1 << 20);
+        for (path, config) in [("/small", small), ("/large", large)] {
+            router.form(Method::POST, path, config, |_, form, _| {
+                let file = form.file("upload").unwrap();
+                Response::text(format!("{} {}", form.field("title").unwrap(), file.size))
+            });
+        }
+        // Far below the uploads, so they can't have been buffered.
+        let server = Server::bind("127.0.0.1:0", router).unwrap().max_body_size(1024).spawn().unwrap();
+
+        let upload = |path: &str, chunked: bool, size: usize| {
+            let mut body = b"--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhi\r\n--XyZ\r\n\
+                Content-Disposition: form-data; name=\"upload\"; filename=\"a.bin\"\r\n\r\n".to_vec();
+            body.extend(std::iter::repeat_n(b'x', size));
+            body.extend_from_slice(b"\r\n--XyZ--\r\n");
+            let framing = match chunked {
+                true => "Transfer-Encoding: chunked".to_string(),
+                false => format!("Content-Length: {}", body.len()),
+            };
+            let mut raw = format!("POST {path} HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n{framing}\r\n\r\n").into_bytes();
+            match chunked {
+                true => {
+                    for chunk in body.chunks(5000) {
+                        raw.extend(format!("{:x}\r\n", chunk.len()).bytes());
+                        raw.extend_from_slice(chunk);
+                        raw.extend_from_slice(b"\r\n");
+                    }
+                    raw.extend_from_slice(b"0\r\n\r\n");
+                }
+                false => raw.extend(body),
+            }
+
+            let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            // The server may answer before it has read everything.
+            let sender = std::thread::spawn(move || { let _ = writer.write_all(&raw); });
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            sender.join().unwrap();
+            response
+        };
+
+        let response = upload("/large", false, 200_000);
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("hi 200000"), "{response}");
+        let response = upload("/large", true, 300_000);
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("hi 300000"), "{response}");
+        let response = upload("/small", false, 4 << 20);
+        assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+
+        server.shutdown();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,362 @@
+use std::fmt;
+
+/// Frame opcodes defined by RFC 6455. The reserved values 3-7 and 11-15 are
+/// rejected while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
+    Continue,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0x0 => Self::Continue,
+            0x1 => Self::Text,
+            0x2 => Self::Binary,
+            0x8 => Self::Close,
+            0x9 => Self::Ping,
+            0xA => Self::Pong,
+            _ => return None,
+        })
+    }
+
+    pub const fn as_u8(&self) -> u8 {
+        match self {
+            Self::Continue => 0x0,
+            Self::Text => 0x1,
+            Self::Binary => 0x2,
+            Self::Close => 0x8,
+            Self::Ping => 0x9,
+            Self::Pong => 0xA,
+        }
+    }
+
+    /// Close, ping and pong frames may not be fragmented and carry at most
+    /// 125 bytes.
+    pub const fn is_control(&self) -> bool {
+        matches!(self, Self::Close | Self::Ping | Self::Pong)
+    }
+}
+
+/// Status code sent in a close frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    pub const NORMAL: Self = Self(1000);
+    pub const GOING_AWAY: Self = Self(1001);
+    pub const PROTOCOL_ERROR: Self = Self(1002);
+    pub const UNSUPPORTED_DATA: Self = Self(1003);
+    /// Reported locally when a close frame carried no code; never sent.
+    pub const NO_STATUS: Self = Self(1005);
+    /// Reported locally when the connection dropped without a close frame; never sent.
+    pub const ABNORMAL: Self = Self(1006);
+    pub const INVALID_PAYLOAD: Self = Self(1007);
+    pub const POLICY_VIOLATION: Self = Self(1008);
+    pub const MESSAGE_TOO_BIG: Self = Self(1009);
+    pub const MANDATORY_EXTENSION: Self = Self(1010);
+    pub const INTERNAL_ERROR: Self = Self(1011);
+
+    /// Whether the code may appear in a close frame on the wire.
+    pub const fn is_sendable(&self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    ReservedOpcode(u8),
+    /// A control frame was fragmented or longer than 125 bytes.
+    InvalidControlFrame,
+    /// The 64-bit length had its most significant bit set.
+    InvalidLength,
+    TooLarge(u64),
+    InvalidClosePayload,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReservedOpcode(opcode) => write!(f, "reserved opcode {opcode:#x}"),
+            Self::InvalidControlFrame => f.write_str("fragmented or oversized control frame"),
+            Self::InvalidLength => f.write_str("invalid payload length"),
+            Self::TooLarge(len) => write!(f, "payload of {len} bytes exceeds the limit"),
+            Self::InvalidClosePayload => f.write_str("malformed close frame payload"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl FrameError {
+    /// Close code to send when a peer's frame fails to decode.
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            Self::TooLarge(_) => CloseCode::MESSAGE_TOO_BIG,
+            _ => CloseCode::PROTOCOL_ERROR,
+        }
+    }
+}
+
+/// One WebSocket frame. `payload` is always stored unmasked; `mask` is the
+/// key used on the wire, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub rsv1: bool,
+    pub rsv2: bool,
+    pub rsv3: bool,
+    pub opcode: OpCode,
+    pub mask: Option<[u8; 4]>,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// A final, unmasked frame.
+    pub fn new(opcode: OpCode, payload: impl Into<Vec<u8>>) -> Self {
+        Self { fin: true, rsv1: false, rsv2: false, rsv3: false, opcode, mask: None, payload: payload.into() }
+    }
+
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::new(OpCode::Text, text.into())
+    }
+
+    pub fn binary(data: impl Into<Vec<u8>>) -> Self {
+        Self::new(OpCode::Binary, data)
+    }
+
+    pub fn ping(data: impl Into<Vec<u8>>) -> Self {
+        Self::new(OpCode::Ping, data)
+    }
+
+    pub fn pong(data: impl Into<Vec<u8>>) -> Self {
+        Self::new(OpCode::Pong, data)
+    }
+
+    /// A close frame. The reason is cut at a character boundary so the
+    /// payload stays within the 125 byte control frame limit.
+    pub fn close(code: Option<CloseCode>, reason: &str) -> Self {
+        let Some(code) = code else {
+            return Self::new(OpCode::Close, Vec::new());
+        };
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = Vec::with_capacity(2 + end);
+        payload.extend_from_slice(&code.0.to_be_bytes());
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        Self::new(OpCode::Close, payload)
+    }
+
+    pub fn fin(mut self, fin: bool) -> Self {
+        self.fin = fin;
+        self
+    }
+
+    pub fn masked(mut self, key: [u8; 4]) -> Self {
+        self.mask = Some(key);
+        self
+    }
+
+    /// Code and reason of a close frame, `None` if it carried neither.
+    pub fn close_reason(&self) -> Result<Option<(CloseCode, String)>, FrameError> {
+        match self.payload.len() {
+            0 => Ok(None),
+            1 => Err(FrameError::InvalidClosePayload),
+            _ => {
+                let code = CloseCode(u16::from_be_bytes([self.payload[0], self.payload[1]]));
+                let reason = std::str::from_utf8(&self.payload[2..]).map_err(|_| FrameError::InvalidClosePayload)?;
+                if !code.is_sendable() {
+                    return Err(FrameError::InvalidClosePayload);
+                }
+                Ok(Some((code, reason.to_string())))
+            }
+        }
+    }
+
+    /// Decodes one frame from the start of `buf`. Returns the frame and the
+    /// number of bytes it used, or `None` if `buf` does not hold a whole frame yet.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, FrameError> {
+        Self::decode_limited(buf, u64::MAX)
+    }
+
+    /// Like [`Frame::decode`], but fails as soon as the header announces a
+    /// payload larger than `max_payload`, before any of it is buffered.
+    pub fn decode_limited(buf: &[u8], max_payload: u64) -> Result<Option<(Self, usize)>, FrameError> {
+        let [first, second, ..] = *buf else {
+            return Ok(None);
+        };
+        let fin = first & 0x80 != 0;
+        let opcode = OpCode::from_u8(first & 0x0F).ok_or(FrameError::ReservedOpcode(first & 0x0F))?;
+        let masked = second & 0x80 != 0;
+
+        let mut offset = 2;
+        let len = match second & 0x7F {
+            126 => {
+                let Some(bytes) = buf.get(2..4) else { return Ok(None) };
+                offset += 2;
+                u16::from_be_bytes([bytes[0], bytes[1]]) as u64
+            }
+            127 => {
+                let Some(&bytes) = buf.get(2..10).and_then(|b| b.first_chunk::<8>()) else { return Ok(None) };
+                offset += 8;
+                let len = u64::from_be_bytes(bytes);
+                if len >> 63 != 0 {
+                    return Err(FrameError::InvalidLength);
+                }
+                len
+            }
+            len => len as u64,
+        };
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(FrameError::InvalidControlFrame);
+        }
+        if len > max_payload {
+            return Err(FrameError::TooLarge(len));
+        }
+
+        let mask = if masked {
+            let Some(key) = buf.get(offset..offset + 4) else { return Ok(None) };
+            offset += 4;
+            Some([key[0], key[1], key[2], key[3]])
+        } else {
+            None
+        };
+
+        let available = (buf.len() - offset) as u64;
+        if available < len {
+            return Ok(None);
+        }
+        let end = offset + len as usize;
+        let mut payload = buf[offset..end].to_vec();
+        if let Some(key) = mask {
+            apply_mask(&mut payload, key);
+        }
+
+        let frame = Self {
+            fin,
+            rsv1: first & 0x40 != 0,
+            rsv2: first & 0x20 != 0,
+            rsv3: first & 0x10 != 0,
+            opcode,
+            mask,
+            payload,
+        };
+        Ok(Some((frame, end)))
+    }
+
+    /// Encodes the frame, masking the payload if a key is set. The shortest
+    /// length form is always used.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.payload.len() + 14);
+        self.encode_into(&mut out);
+        out
+    }
+
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        let mut first = self.opcode.as_u8();
+        for (set, bit) in [(self.fin, 0x80), (self.rsv1, 0x40), (self.rsv2, 0x20), (self.rsv3, 0x10)] {
+            if set {
+                first |= bit;
+            }
+        }
+        out.push(first);
+
+        let mask_bit = if self.mask.is_some() { 0x80 } else { 0 };
+        match self.payload.len() {
+            len @ 0..=125 => out.push(mask_bit | len as u8),
+            len @ 126..=0xFFFF => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        let start = out.len();
+        if let Some(key) = self.mask {
+            out.extend_from_slice(&key);
+            out.extend_from_slice(&self.payload);
+            apply_mask(&mut out[start + 4..], key);
+        } else {
+            out.extend_from_slice(&self.payload);
+        }
+    }
+}
+
+/// XORs `data` with the masking key. Masking and unmasking are the same operation.
+pub fn apply_mask(data: &mut [u8], key: [u8; 4]) {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= key[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rand::hash;
+
+    #[test]
+    fn round_trips_all_length_forms() {
+        for len in [0, 1, 125, 126, 0xFFFF, 0x10000] {
+            let frame = Frame::binary(vec![0xAB; len]).masked([1, 2, 3, 4]);
+            let bytes = frame.encode();
+            assert_eq!(Frame::decode(&bytes).unwrap(), Some((frame, bytes.len())));
+            assert_eq!(Frame::decode(&bytes[..bytes.len() - 1]).unwrap(), None);
+        }
+        // The RFC example: a masked "Hello".
+        let hello = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, used) = Frame::decode(&hello).unwrap().unwrap();
+        assert_eq!((frame.payload.as_slice(), used), (&b"Hello"[..], hello.len()));
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        assert_eq!(Frame::decode(&[0x83, 0x00]), Err(FrameError::ReservedOpcode(3)));
+        assert_eq!(Frame::decode(&[0x09, 0x00]), Err(FrameError::InvalidControlFrame));
+        assert_eq!(Frame::decode(&[0x89, 0x7E, 0x00, 0x7E]), Err(FrameError::InvalidControlFrame));
+        assert_eq!(Frame::decode(&[0x82, 0x7F, 0x80, 0, 0, 0, 0, 0, 0, 0]), Err(FrameError::InvalidLength));
+        assert_eq!(Frame::decode_limited(&[0x82, 0x7E, 0x01, 0x00], 255), Err(FrameError::TooLarge(256)));
+
+        let close = Frame::close(Some(CloseCode::GOING_AWAY), &"ä".repeat(100));
+        assert_eq!(close.payload.len(), 124);
+        assert_eq!(close.close_reason().unwrap().unwrap().0, CloseCode::GOING_AWAY);
+        assert_eq!(Frame::new(OpCode::Close, vec![0x03, 0xED]).close_reason(), Err(FrameError::InvalidClosePayload));
+    }
+
+    #[test]
+    fn decodes_arbitrary_bytes_without_panicking() {
+        let mut seed = 7;
+        let mut bytes = Vec::new();
+        for _ in 0..2000 {
+            bytes.clear();
+            seed = hash(seed);
+            for _ in 0..seed % 64 {
+                seed = hash(seed);
+                bytes.push(seed as u8);
+            }
+            match Frame::decode(&bytes) {
+                Ok(Some((frame, used))) => {
+                    assert!(used <= bytes.len());
+                    let encoded = frame.encode();
+                    assert_eq!(Frame::decode(&encoded).unwrap(), Some((frame, encoded.len())));
+                }
+                Ok(None) | Err(_) => (),
+            }
+        }
+    }
+}
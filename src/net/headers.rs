@@ -0,0 +1,97 @@
+use std::{borrow::Cow, fmt};
+
+/// Ordered list of HTTP header fields with case-insensitive name lookup.
+///
+/// Duplicate names are kept in arrival order, which matters for fields like
+/// `Set-Cookie` that may not be folded into one line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the first value stored under `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter().filter(move |(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Checks whether a comma separated header like `Connection` lists `token`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).flat_map(|v| v.split(',')).any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Replaces every value stored under `name` with `value`.
+    ///
+    /// CR, LF and NUL are dropped from both, so a value taken from user input
+    /// can't smuggle in extra header lines.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = sanitize(name.into());
+        self.remove(&name);
+        self.entries.push((name, sanitize(value.into())));
+    }
+
+    /// Adds another value for `name` without touching existing ones.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((sanitize(name.into()), sanitize(value.into())));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.get("Content-Length").and_then(|v| v.trim().parse().ok())
+    }
+}
+
+fn sanitize(mut field: String) -> String {
+    if field.contains(LINE_BREAKS) {
+        field.retain(|c| !LINE_BREAKS.contains(&c));
+    }
+    field
+}
+
+/// Characters that would end or corrupt the line a field is written on.
+const LINE_BREAKS: [char; 3] = ['\r', '\n', '\0'];
+
+/// `field` without the characters that could smuggle in extra lines.
+pub(crate) fn strip_line_breaks(field: &str) -> Cow<'_, str> {
+    if field.contains(LINE_BREAKS) {
+        Cow::Owned(field.replace(LINE_BREAKS, ""))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+impl fmt::Display for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.entries {
+            write!(f, "{name}: {value}\r\n")?;
+        }
+        Ok(())
+    }
+}
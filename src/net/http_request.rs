@@ -1,47 +1,501 @@
+use std::fmt;
 
+use super::Headers;
 
-#[allow(unused)]
+/// Upper bound for the request line plus all header fields.
+pub(crate) const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Longest chunk-size line, extensions included, that a chunked body may use.
+const MAX_CHUNK_LINE_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    GET,
+    POST,
+    PUT,
+    DELETE,
+    PATCH,
+    HEAD,
+    OPTIONS,
+}
+
+impl Method {
+    pub fn parse(method: &str) -> Option<Self> {
+        Some(match method {
+            "GET" => Self::GET,
+            "POST" => Self::POST,
+            "PUT" => Self::PUT,
+            "DELETE" => Self::DELETE,
+            "PATCH" => Self::PATCH,
+            "HEAD" => Self::HEAD,
+            "OPTIONS" => Self::OPTIONS,
+            _ => return None,
+        })
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::GET => "GET",
+            Self::POST => "POST",
+            Self::PUT => "PUT",
+            Self::DELETE => "DELETE",
+            Self::PATCH => "PATCH",
+            Self::HEAD => "HEAD",
+            Self::OPTIONS => "OPTIONS",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The buffer ends before the request is complete.
+    Incomplete,
+    InvalidRequestLine,
+    UnknownMethod(String),
+    UnsupportedVersion(String),
+    InvalidHeader,
+    InvalidContentLength,
+    InvalidChunk,
+    /// Bad percent escape or the decoded bytes are not UTF-8.
+    InvalidEncoding,
+    /// The message head or chunk trailers exceed the size limit.
+    TooLarge,
+    /// The announced or decoded body exceeds the size limit.
+    BodyTooLarge,
+    InvalidStatusLine,
+    InvalidUrl,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Incomplete => f.write_str("incomplete request"),
+            Self::InvalidRequestLine => f.write_str("malformed request line"),
+            Self::UnknownMethod(method) => write!(f, "unknown method `{method}`"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported protocol version `{version}`"),
+            Self::InvalidHeader => f.write_str("malformed header field"),
+            Self::InvalidContentLength => f.write_str("invalid Content-Length"),
+            Self::InvalidChunk => f.write_str("malformed chunked body"),
+            Self::InvalidEncoding => f.write_str("invalid percent encoding"),
+            Self::TooLarge => f.write_str("message head too large"),
+            Self::BodyTooLarge => f.write_str("message body too large"),
+            Self::InvalidStatusLine => f.write_str("malformed status line"),
+            Self::InvalidUrl => f.write_str("invalid URL"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HTTPRequest {
-    pub request: RequestType,
+    pub method: Method,
+    /// Percent-decoded path without the query string.
+    pub path: String,
+    /// Raw query string as it appeared after `?`.
+    pub query: Option<String>,
+    /// Decoded `name=value` pairs of the query string.
+    pub query_pairs: Vec<(String, String)>,
+    pub version: String,
+    pub headers: Headers,
     pub host: Option<String>,
+    pub body: Vec<u8>,
 }
 
 impl HTTPRequest {
-    pub fn parse(buf: &[u8]) -> Option<Self> {
+    /// Builds an outgoing request for `target`, which may contain a query.
+    pub fn new(method: Method, target: &str) -> Self {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target.to_string(), None),
+        };
+        let query_pairs = query.as_deref().and_then(|q| parse_query(q).ok()).unwrap_or_default();
+        Self {
+            method,
+            path,
+            query,
+            query_pairs,
+            version: "HTTP/1.1".to_string(),
+            headers: Headers::new(),
+            host: None,
+            body: Vec::new(),
+        }
+    }
 
-        let text_form = String::from_utf8_lossy(buf);
+    /// Serializes the request. `path` is sent as stored, so it must already be
+    /// in its encoded form. `Host` and `Content-Length` are added if missing.
+    pub fn write_to(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+        let mut head = format!("{} {}", self.method, self.path);
+        if let Some(query) = &self.query {
+            head.push('?');
+            head.push_str(query);
+        }
+        head.push_str(&format!(" {}\r\n", self.version));
+        if let Some(host) = &self.host
+            && !self.headers.contains("Host")
+        {
+            head.push_str(&format!("Host: {host}\r\n"));
+        }
+        head.push_str(&self.headers.to_string());
+        let has_body = !self.body.is_empty() || matches!(self.method, Method::POST | Method::PUT | Method::PATCH);
+        if has_body && !self.headers.contains("Content-Length") && !self.headers.contains("Transfer-Encoding") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
 
-        let mut request = RequestType::GET(String::with_capacity(0), None);
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&self.body)
+    }
 
-        for line in text_form.lines() {
-            if line.starts_with("GET ") {
-                let req = line[4..].split_whitespace().find(|x| x.starts_with("/")).unwrap_or("").replace("%20", " ");
-                let req = req.split_once("?").unwrap_or((&req, ""));
-                let path = req.0.to_string();
-                let query;
+    /// Parses one complete request from `buf`.
+    pub fn parse(buf: &[u8]) -> Result<Self, ParseError> {
+        match Self::parse_partial(buf)? {
+            Some((request, _)) => Ok(request),
+            None => Err(ParseError::Incomplete),
+        }
+    }
 
-                if req.1 == "" {
-                    query = None;
-                } else {
-                    query = Some(req.1.to_string());
-                }
+    /// Parses the first request in `buf`.
+    ///
+    /// Returns `Ok(None)` while more bytes are needed, otherwise the request and
+    /// the number of bytes it occupied so pipelined requests can follow.
+    pub fn parse_partial(buf: &[u8]) -> Result<Option<(Self, usize)>, ParseError> {
+        Self::parse_limited(buf, MAX_HEAD_LEN, usize::MAX)
+    }
 
-                request = RequestType::GET(path, query);
+    /// [`HTTPRequest::parse_partial`] with limits for the head and the body.
+    /// An oversized body is refused as soon as its length is known, before it
+    /// has arrived.
+    pub(crate) fn parse_limited(buf: &[u8], max_head: usize, max_body: usize) -> Result<Option<(Self, usize)>, ParseError> {
+        let Some((mut request, body_start)) = Self::parse_head(buf, max_head)? else {
+            return Ok(None);
+        };
+        let headers = &request.headers;
 
-            } else if line.starts_with("Sec-WebSocket-Key") {
-                request = RequestType::UpgradeWs(line[19..].to_string());
-                break;
+        let rest = &buf[body_start..];
+        let (body, body_len) = if headers.has_token("Transfer-Encoding", "chunked") {
+            match decode_chunked(rest, max_head, max_body)? {
+                Some(decoded) => decoded,
+                None => return Ok(None),
             }
+        } else if let Some(len) = request.content_length()? {
+            if len > max_body {
+                return Err(ParseError::BodyTooLarge);
+            }
+            if rest.len() < len {
+                return Ok(None);
+            }
+            (rest[..len].to_vec(), len)
+        } else {
+            (Vec::new(), 0)
+        };
+
+        request.body = body;
+        Ok(Some((request, body_start + body_len)))
+    }
+
+    /// The `Content-Length` of the body, which all copies of the header must agree on.
+    pub(crate) fn content_length(&self) -> Result<Option<usize>, ParseError> {
+        let Some(len) = self.headers.get("Content-Length") else {
+            return Ok(None);
+        };
+        let len: usize = len.parse().map_err(|_| ParseError::InvalidContentLength)?;
+        if self.headers.get_all("Content-Length").any(|v| v.parse() != Ok(len)) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        Ok(Some(len))
+    }
+
+    /// Parses the head of the first request in `buf`, leaving `body` empty.
+    /// Also returns the offset of the body.
+    pub(crate) fn parse_head(buf: &[u8], max_head: usize) -> Result<Option<(Self, usize)>, ParseError> {
+        let (head_len, body_start) = match find_head_end(buf) {
+            Some(end) => end,
+            None if buf.len() > max_head => return Err(ParseError::TooLarge),
+            None => return Ok(None),
+        };
+        if head_len > max_head {
+            return Err(ParseError::TooLarge);
+        }
+
+        let head = std::str::from_utf8(&buf[..head_len]).map_err(|_| ParseError::InvalidEncoding)?;
+        let mut lines = head.lines();
+
+        let request_line = lines.next().ok_or(ParseError::InvalidRequestLine)?;
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version), None) if !target.is_empty() => (method, target, version),
+            _ => return Err(ParseError::InvalidRequestLine),
+        };
+
+        let method = Method::parse(method).ok_or_else(|| ParseError::UnknownMethod(method.to_string()))?;
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(ParseError::UnsupportedVersion(version.to_string()));
         }
 
-        
-        Some(Self { request, host: None})
+        let headers = parse_headers(lines)?;
+
+        // Absolute-form targets carry the authority themselves.
+        let mut host = headers.get("Host").map(str::to_string);
+        let target = match target.strip_prefix("http://").or_else(|| target.strip_prefix("https://")) {
+            Some(rest) => {
+                let (authority, path) = rest.find('/').map_or((rest, "/"), |i| (&rest[..i], &rest[i..]));
+                host = Some(authority.to_string());
+                path
+            }
+            None => target,
+        };
+
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+        let path = percent_decode(path, false)?;
+        let query_pairs = match &query {
+            Some(query) => parse_query(query)?,
+            None => Vec::new(),
+        };
+
+        let request = Self {
+            method,
+            path,
+            query,
+            query_pairs,
+            version: version.to_string(),
+            headers,
+            host,
+            body: Vec::new(),
+        };
+        Ok(Some((request, body_start)))
+    }
+
+    /// Looks up the first decoded query parameter called `name`.
+    pub fn query_value(&self, name: &str) -> Option<&str> {
+        self.query_pairs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Whether the client wants the connection to stay open after the response.
+    /// HTTP/1.1 defaults to persistent connections, HTTP/1.0 has to ask.
+    pub fn keep_alive(&self) -> bool {
+        if self.headers.has_token("Connection", "close") {
+            false
+        } else {
+            self.version == "HTTP/1.1" || self.headers.has_token("Connection", "keep-alive")
+        }
+    }
+
+    /// The `Sec-WebSocket-Key` of an upgrade request, if this is one.
+    pub fn websocket_key(&self) -> Option<&str> {
+        if self.method == Method::GET && self.headers.has_token("Upgrade", "websocket") {
+            self.headers.get("Sec-WebSocket-Key")
+        } else {
+            None
+        }
     }
 }
 
-#[allow(unused)]
-pub enum RequestType {
-    GET(String, Option<String>),
-    POST,
-    UpgradeWs(String)
-}
\ No newline at end of file
+/// Parses `name: value` lines up to the end of a message head.
+pub(crate) fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+    for line in lines {
+        if line.starts_with([' ', '\t']) {
+            // Obsolete line folding is rejected as RFC 9112 allows.
+            return Err(ParseError::InvalidHeader);
+        }
+        let (name, value) = line.split_once(':').ok_or(ParseError::InvalidHeader)?;
+        if name.is_empty() || name.bytes().any(|b| b.is_ascii_whitespace() || b.is_ascii_control()) {
+            return Err(ParseError::InvalidHeader);
+        }
+        headers.append(name, value.trim());
+    }
+    Ok(headers)
+}
+
+/// Returns the length of the head without the blank line and the offset of the body.
+pub(crate) fn find_head_end(buf: &[u8]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    while let Some(pos) = buf[i..].iter().position(|&b| b == b'\n') {
+        let nl = i + pos;
+        match &buf[nl + 1..] {
+            [b'\r', b'\n', ..] => return Some((nl + 1, nl + 3)),
+            [b'\n', ..] => return Some((nl + 1, nl + 2)),
+            _ => i = nl + 1,
+        }
+    }
+    None
+}
+
+/// Decodes a chunked body. Returns the payload and the bytes consumed, or
+/// `None` if the terminating chunk has not arrived yet. The payload may not
+/// exceed `max_body` and the trailer fields not `max_trailers` bytes.
+pub(crate) fn decode_chunked(buf: &[u8], max_trailers: usize, max_body: usize) -> Result<Option<(Vec<u8>, usize)>, ParseError> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let Some(line_len) = buf[pos..].iter().position(|&b| b == b'\n') else {
+            if buf.len() - pos > MAX_CHUNK_LINE_LEN {
+                return Err(ParseError::InvalidChunk);
+            }
+            return Ok(None);
+        };
+        if line_len > MAX_CHUNK_LINE_LEN {
+            return Err(ParseError::InvalidChunk);
+        }
+        let line = std::str::from_utf8(&buf[pos..pos + line_len]).map_err(|_| ParseError::InvalidChunk)?;
+        let size = line.trim_end_matches('\r').split(';').next().unwrap_or("").trim();
+        let size = parse_hex(size).ok_or(ParseError::InvalidChunk)?;
+        pos += line_len + 1;
+
+        if size == 0 {
+            // Skip trailer fields up to the final empty line.
+            let trailers = pos;
+            loop {
+                if pos - trailers > max_trailers {
+                    return Err(ParseError::TooLarge);
+                }
+                let Some(line_len) = buf[pos..].iter().position(|&b| b == b'\n') else {
+                    if buf.len() - trailers > max_trailers {
+                        return Err(ParseError::TooLarge);
+                    }
+                    return Ok(None);
+                };
+                let empty = buf[pos..pos + line_len].iter().all(|&b| b == b'\r');
+                pos += line_len + 1;
+                if empty {
+                    return Ok(Some((body, pos)));
+                }
+            }
+        }
+
+        if size > max_body - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+        // The chunk and at least the start of its line break. Compared without
+        // adding, as a huge size would overflow.
+        if buf.len() - pos <= size {
+            return Ok(None);
+        }
+        let end = pos + size;
+        body.extend_from_slice(&buf[pos..end]);
+        pos = end;
+        match &buf[pos..] {
+            [b'\r', b'\n', ..] => pos += 2,
+            [b'\n', ..] => pos += 1,
+            [b'\r'] => return Ok(None),
+            _ => return Err(ParseError::InvalidChunk),
+        }
+    }
+}
+
+/// Parses bare hex digits. Unlike `from_str_radix`, a leading `+` is refused.
+fn parse_hex(digits: &str) -> Option<usize> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    usize::from_str_radix(digits, 16).ok()
+}
+
+/// Decodes `%XX` escapes. With `plus_as_space` a `+` becomes a space as in
+/// `application/x-www-form-urlencoded`.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Result<String, ParseError> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).ok_or(ParseError::InvalidEncoding)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| ParseError::InvalidEncoding)?;
+                out.push(parse_hex(hex).ok_or(ParseError::InvalidEncoding)? as u8);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| ParseError::InvalidEncoding)
+}
+
+/// Splits a query string into decoded `name=value` pairs.
+pub fn parse_query(query: &str) -> Result<Vec<(String, String)>, ParseError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(name, true)?, percent_decode(value, true)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_headers_and_query() {
+        let raw = b"GET /some%20dir/f%C3%BC.txt?a=1&b=hello+world%21 HTTP/1.1\r\nhost: example.org\r\nX-Test:  yes \r\n\r\n";
+        let request = HTTPRequest::parse(raw).unwrap();
+        assert_eq!(request.method, Method::GET);
+        assert_eq!(request.path, "/some dir/f\u{fc}.txt");
+        assert_eq!(request.query_value("b"), Some("hello world!"));
+        assert_eq!(request.host.as_deref(), Some("example.org"));
+        assert_eq!(request.headers.get("x-test"), Some("yes"));
+    }
+
+    #[test]
+    fn parses_bodies() {
+        let raw = b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET";
+        let (request, used) = HTTPRequest::parse_partial(raw).unwrap().unwrap();
+        assert_eq!(request.body, b"hello");
+        assert_eq!(used, raw.len() - 3);
+
+        let raw = b"PUT /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nTrailer: x\r\n\r\n";
+        assert_eq!(HTTPRequest::parse(raw).unwrap().body, b"Wikipedia");
+        assert_eq!(HTTPRequest::parse(&raw[..raw.len() - 2]), Err(ParseError::Incomplete));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(HTTPRequest::parse(b"BREW /pot HTTP/1.1\r\n\r\n"), Err(ParseError::UnknownMethod("BREW".into())));
+        assert_eq!(HTTPRequest::parse(b"GET /\r\n\r\n"), Err(ParseError::InvalidRequestLine));
+        assert_eq!(HTTPRequest::parse(b"GET / HTTP/1.1\r\nbroken\r\n\r\n"), Err(ParseError::InvalidHeader));
+        assert_eq!(HTTPRequest::parse(b"GET /%zz HTTP/1.1\r\n\r\n"), Err(ParseError::InvalidEncoding));
+        assert_eq!(HTTPRequest::parse(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), Err(ParseError::InvalidContentLength));
+        assert_eq!(HTTPRequest::parse(b"GET /%+f HTTP/1.1\r\n\r\n"), Err(ParseError::InvalidEncoding));
+    }
+
+    #[test]
+    fn rejects_hostile_chunk_sizes() {
+        // `pos + size` lands on usize::MAX here, which used to overflow or index out of range.
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffed\r\nabc";
+        assert_eq!(HTTPRequest::parse_partial(raw), Ok(None));
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n10000000000000000\r\n";
+        assert_eq!(HTTPRequest::parse_partial(raw), Err(ParseError::InvalidChunk));
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+3\r\nabc\r\n0\r\n\r\n";
+        assert_eq!(HTTPRequest::parse_partial(raw), Err(ParseError::InvalidChunk));
+
+        // Endless chunk extensions or trailers can't grow the buffer forever.
+        let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1;{}", "x".repeat(2000));
+        assert_eq!(HTTPRequest::parse_partial(raw.as_bytes()), Err(ParseError::InvalidChunk));
+        let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX: {}", "x".repeat(200));
+        assert_eq!(HTTPRequest::parse_limited(raw.as_bytes(), 100, 10), Err(ParseError::TooLarge));
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\n";
+        assert_eq!(HTTPRequest::parse_limited(raw, 100, 10), Err(ParseError::BodyTooLarge));
+    }
+}
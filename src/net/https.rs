@@ -1,26 +1,16 @@
-use std::{fs, io::Cursor, path::PathBuf};
+use std::{fs, io::{self, Cursor, Write}, path::{Path, PathBuf}};
 use zip::write::FileOptions;
 use zip::ZipWriter;
 
+use super::{mime, Body, Response, ZipOptions, ZipStreamWriter};
 
-pub struct HTTPS {
-    pub http_verion: Option<String>,
-    pub host: Option<String>,
-    pub status: Option<String>
-}
+
+pub struct HTTPS;
 
 impl HTTPS {
     pub fn format(content_type: &[u8], content: &[u8]) -> Vec<u8> {
-        let content_len = content.len();
-        let mut format = Vec::with_capacity(content_len + 100);
-        format.extend_from_slice(b"HTTP/1.1 200 OK\r\n");
-        format.extend_from_slice(b"Content-Type: ");
-        format.extend_from_slice(content_type);
-        format.extend_from_slice(b"\r\ncharset=UTF-8\r\nContent-Length: ");
-        format.extend_from_slice(content_len.to_string().as_bytes());
-        format.extend_from_slice(b"\r\n\r\n");
-        format.extend_from_slice(content);
-        format
+        let content_type = mime::content_type_for_mime(&String::from_utf8_lossy(content_type));
+        Response::ok().content_type(&content_type).body(content).to_bytes().unwrap_or_default()
     }
 
     pub fn format_content(path: PathBuf) -> Option<Vec<u8>> {
@@ -29,18 +19,11 @@ impl HTTPS {
             match Self::zip_directory(&path) {
                 Ok(zip_data) => {
                     let zip_filename = format!("{}.zip", path.file_name()?.to_string_lossy());
-                    let headers = format!(
-                        "HTTP/1.1 200 OK\r\n\
-                        Content-Type: application/zip\r\n\
-                        Content-Disposition: attachment; filename=\"{}\"\r\n\
-                        Content-Length: {}\r\n\r\n",
-                        zip_filename,
-                        zip_data.len()
-                    );
-
-                    let mut response = headers.into_bytes();
-                    response.extend_from_slice(&zip_data);
-                    return Some(response);
+                    let response = Response::ok()
+                        .content_type("application/zip")
+                        .header("Content-Disposition", format!("attachment; filename=\"{zip_filename}\""))
+                        .body(zip_data);
+                    return response.to_bytes().ok();
                 }
                 Err(_) => return None,
             }
@@ -49,36 +32,40 @@ impl HTTPS {
         let file_content = fs::read(&path);
         match file_content {
             Ok(content) => {
-                let content_type: &[u8] = match path.extension() {
-                    Some(extention) => match extention.as_encoded_bytes() {
-                        b"apng" => b"image/apng",
-                        b"png" => b"image/png",
-                        b"webp" => b"image/webp",
-                        b"gif" => b"image/gif",
-                        b"jpeg" => b"image/jpeg",
-                        b"svh" => b"image/svg+xml",
-                        b"avif" => b"image/avif",
-                        b"zip" => b"application/zip",
-                        b"json" => b"text/json",
-                        b"js" => b"text/javascript",
-                        b"wasm" => b"application/wasm",
-                        b"html" => b"text/html",
-                        b"pdf" => b"application/pdf",
-                        b"mp3" => b"audio/mpeg",
-                        b"mp4" => b"audio/mp4",
-                        b"ogg" => b"audio/ogg",
-                        b"wav" => b"audio/wav",
-                        b"ico" => b"image/vnd.microsoft.icon",
-                        _ => b"text/plain",
-                    },
-                    None => b"text/html",
-                };
-                return Some(HTTPS::format(content_type, &content));
+                return Some(HTTPS::format(mime::mime_for_path(&path).as_bytes(), &content));
             }
             Err(_) => return None,
         }
     }
     
+    /// Download response that zips `dir` while it is being sent, using chunked
+    /// transfer encoding instead of building the archive in memory.
+    pub fn zip_response(dir: &Path, options: ZipOptions) -> Option<Response> {
+        if !dir.is_dir() {
+            return None;
+        }
+        let zip_filename = format!("{}.zip", dir.file_name()?.to_string_lossy());
+        let dir = dir.to_path_buf();
+        let body = Body::stream(move |writer| {
+            let mut zip = ZipStreamWriter::new(writer);
+            zip.add_directory_contents(&dir, &options)?;
+            zip.finish().map(|_| ())
+        });
+
+        Some(Response::ok()
+            .content_type("application/zip")
+            .header("Content-Disposition", format!("attachment; filename=\"{zip_filename}\""))
+            .body(body))
+    }
+
+    /// Writes a complete streamed ZIP download of `dir` to `stream`.
+    pub fn write_zip_directory(stream: &mut impl Write, dir: &Path, options: ZipOptions) -> io::Result<()> {
+        match Self::zip_response(dir, options) {
+            Some(response) => response.write_to(stream),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "not a directory")),
+        }
+    }
+
     /// Verzeichnis rekursiv in eine ZIP-Datei packen
     fn zip_directory(dir: &PathBuf) -> Result<Vec<u8>, std::io::Error> {
         let mut zip_data = Vec::new();
@@ -96,14 +83,15 @@ impl HTTPS {
             for entry in fs::read_dir(dir)? {
                 let entry = entry?;
                 let path = entry.path();
-                let relative_path = path.strip_prefix(base_path).unwrap();
+                let Ok(relative_path) = path.strip_prefix(base_path) else { continue };
+                let name = relative_path.to_string_lossy();
 
                 if path.is_dir() {
-                    zip.add_directory(relative_path.to_str().unwrap(), options)?;
+                    zip.add_directory(name, options)?;
                     add_dir_to_zip(zip, &path, base_path, options)?;
                 } else {
                     let mut file = fs::File::open(&path)?;
-                    zip.start_file(relative_path.to_str().unwrap(), options)?;
+                    zip.start_file(name, options)?;
                     std::io::copy(&mut file, zip)?;
                 }
             }
@@ -0,0 +1,295 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use super::{CloseCode, ConnectionId, Message, Reactor, Result, WebSocket, WebSocketInterface};
+
+/// What to do with a client whose send queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Skip messages for that client until its queue drains.
+    DropMessages,
+    /// Drop the connection without a close handshake.
+    Disconnect,
+}
+
+#[derive(Default)]
+struct Members {
+    clients: HashSet<ConnectionId>,
+    rooms: HashMap<String, HashSet<ConnectionId>>,
+}
+
+/// Tracks the connections of a [`Reactor`] and fans messages out to all of
+/// them or to named rooms.
+///
+/// Delivery happens on the reactor threads; a client that has more than
+/// `max_queue` frames waiting is handled by the [`SlowConsumerPolicy`].
+pub struct Hub {
+    reactor: Arc<Reactor>,
+    members: Arc<Mutex<Members>>,
+    max_queue: usize,
+    policy: SlowConsumerPolicy,
+}
+
+impl Hub {
+    pub fn new(reactor: Arc<Reactor>) -> Self {
+        Self { reactor, members: Arc::default(), max_queue: 256, policy: SlowConsumerPolicy::DropMessages }
+    }
+
+    pub fn max_queue(mut self, max_queue: usize) -> Self {
+        self.max_queue = max_queue.max(1);
+        self
+    }
+
+    pub fn policy(mut self, policy: SlowConsumerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Adds a connection to the reactor and the hub. It leaves the hub and
+    /// all its rooms when it closes.
+    pub fn add(&self, client: impl WebSocketInterface + Send + 'static) -> Result<ConnectionId> {
+        let id = self.reactor.next_id();
+        self.members().clients.insert(id);
+        let member = Member { client, id, members: self.members.clone() };
+        if let Err(e) = self.reactor.insert(id, member) {
+            self.remove(id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Forgets a connection without closing it.
+    pub fn remove(&self, id: ConnectionId) {
+        remove(&mut self.members(), id);
+    }
+
+    pub fn contains(&self, id: ConnectionId) -> bool {
+        self.members().clients.contains(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members().clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `false` if the connection is not part of the hub.
+    pub fn join(&self, id: ConnectionId, room: &str) -> bool {
+        let mut members = self.members();
+        if !members.clients.contains(&id) {
+            return false;
+        }
+        members.rooms.entry(room.to_string()).or_default().insert(id);
+        true
+    }
+
+    pub fn leave(&self, id: ConnectionId, room: &str) {
+        let mut members = self.members();
+        if let Some(room_members) = members.rooms.get_mut(room) {
+            room_members.remove(&id);
+            if room_members.is_empty() {
+                members.rooms.remove(room);
+            }
+        }
+    }
+
+    pub fn room_members(&self, room: &str) -> Vec<ConnectionId> {
+        let members = self.members();
+        members.rooms.get(room).map_or_else(Vec::new, |room| room.iter().copied().collect())
+    }
+
+    pub fn send(&self, id: ConnectionId, message: Message) -> Result<()> {
+        let (max_queue, policy) = (self.max_queue, self.policy);
+        self.reactor.with(id, move |client| enqueue(client.websocket_mut(), message, max_queue, policy))
+    }
+
+    /// Sends to every connection. Returns how many it was queued for.
+    pub fn broadcast(&self, message: Message) -> usize {
+        let targets: Vec<_> = self.members().clients.iter().copied().collect();
+        self.send_all(targets, message)
+    }
+
+    /// Sends to every connection in `room`. Returns how many it was queued for.
+    pub fn broadcast_to(&self, room: &str, message: Message) -> usize {
+        let targets = self.room_members(room);
+        self.send_all(targets, message)
+    }
+
+    fn members(&self) -> MutexGuard<'_, Members> {
+        lock(&self.members)
+    }
+
+    fn send_all(&self, targets: Vec<ConnectionId>, message: Message) -> usize {
+        targets.into_iter().filter(|&id| self.send(id, message.clone()).is_ok()).count()
+    }
+}
+
+/// Queues `message` unless the client is already `max_queue` frames behind.
+fn enqueue(ws: &mut WebSocket, message: Message, max_queue: usize, policy: SlowConsumerPolicy) {
+    if ws.queued() < max_queue {
+        if ws.send_message(message).is_err() {
+            ws.disconnect();
+        }
+    } else if policy == SlowConsumerPolicy::Disconnect {
+        ws.disconnect();
+    }
+}
+
+/// Membership stays consistent even if a panic poisoned the lock.
+fn lock(members: &Mutex<Members>) -> MutexGuard<'_, Members> {
+    members.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn remove(members: &mut Members, id: ConnectionId) {
+    members.clients.remove(&id);
+    members.rooms.retain(|_, room| {
+        room.remove(&id);
+        !room.is_empty()
+    });
+}
+
+/// Wraps a client so the hub notices when it closes.
+struct Member<I> {
+    client: I,
+    id: ConnectionId,
+    members: Arc<Mutex<Members>>,
+}
+
+impl<I: WebSocketInterface> WebSocketInterface for Member<I> {
+    fn on_message(&mut self, message: Message) {
+        self.client.on_message(message);
+    }
+
+    fn on_ping(&mut self, data: &[u8]) {
+        self.client.on_ping(data);
+    }
+
+    fn on_pong(&mut self, data: &[u8]) {
+        self.client.on_pong(data);
+    }
+
+    fn on_closed(&self, ip: SocketAddr, code: CloseCode, reason: &str) {
+        remove(&mut lock(&self.members), self.id);
+        self.client.on_closed(ip, code, reason);
+    }
+
+    fn websocket(&self) -> &WebSocket {
+        self.client.websocket()
+    }
+
+    fn websocket_mut(&mut self) -> &mut WebSocket {
+        self.client.websocket_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{Router, Server};
+    use std::{net::{TcpListener, TcpStream}, io::Read, sync::{mpsc::{channel, Sender}, RwLock}, thread};
+
+    struct Listener {
+        ws: WebSocket,
+    }
+
+    impl WebSocketInterface for Listener {
+        fn on_message(&mut self, _message: Message) {}
+        fn on_closed(&self, _ip: SocketAddr, _code: CloseCode, _reason: &str) {}
+        fn websocket(&self) -> &WebSocket {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket {
+            &mut self.ws
+        }
+    }
+
+    struct Collector {
+        ws: WebSocket,
+        seen: Vec<Message>,
+        done: Sender<Vec<Message>>,
+    }
+
+    impl WebSocketInterface for Collector {
+        fn on_message(&mut self, message: Message) {
+            let end = message == Message::Text("end".into());
+            self.seen.push(message);
+            if end {
+                let _ = self.done.send(std::mem::take(&mut self.seen));
+                self.ws.close().unwrap();
+            }
+        }
+        fn on_closed(&self, _ip: SocketAddr, _code: CloseCode, _reason: &str) {}
+        fn websocket(&self) -> &WebSocket {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket {
+            &mut self.ws
+        }
+    }
+
+    #[test]
+    fn broadcasts_to_rooms_and_forgets_closed_clients() {
+        let hub = Arc::new(Hub::new(Arc::new(Reactor::new(2).unwrap())));
+        let (ids, added) = channel();
+        let ids = Mutex::new(ids);
+        let mut router = Router::new();
+        let shared = hub.clone();
+        router.websocket("/", move |ws, _, _| {
+            let id = shared.add(Listener { ws }).unwrap();
+            let _ = ids.lock().unwrap().send(id);
+        });
+        let server = Server::bind("127.0.0.1:0", router).unwrap().spawn().unwrap();
+        let url = format!("ws://{}/", server.local_addr());
+
+        let mut clients = Vec::new();
+        let mut members = Vec::new();
+        for _ in 0..3 {
+            let ws = WebSocket::connect(&url).unwrap();
+            members.push(added.recv().unwrap());
+            let (done, seen) = channel();
+            thread::spawn(move || WebSocket::run(Arc::new(RwLock::new(Collector { ws, seen: Vec::new(), done }))));
+            clients.push(seen);
+        }
+
+        assert!(hub.join(members[0], "a") && hub.join(members[1], "a"));
+        assert_eq!(hub.broadcast_to("a", Message::Text("room".into())), 2);
+        assert_eq!(hub.broadcast(Message::Text("end".into())), 3);
+
+        let room = vec![Message::Text("room".into()), Message::Text("end".into())];
+        assert_eq!(clients[0].recv().unwrap(), room);
+        assert_eq!(clients[1].recv().unwrap(), room);
+        assert_eq!(clients[2].recv().unwrap(), vec![Message::Text("end".into())]);
+
+        // Closed clients leave the hub and their rooms.
+        for _ in 0..100 {
+            if hub.is_empty() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(hub.is_empty());
+        assert!(hub.room_members("a").is_empty());
+        server.shutdown();
+    }
+
+    #[test]
+    fn applies_slow_consumer_policy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut ws = WebSocket::new(listener.accept().unwrap().0);
+
+        for _ in 0..3 {
+            enqueue(&mut ws, Message::Text("x".into()), 2, SlowConsumerPolicy::DropMessages);
+        }
+        assert_eq!(ws.queued(), 2);
+
+        enqueue(&mut ws, Message::Text("x".into()), 2, SlowConsumerPolicy::Disconnect);
+        assert_eq!(ws.queued(), 0);
+        assert_eq!(peer.read(&mut [0; 16]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,36 @@
+use std::{fmt, sync::RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+type Logger = Box<dyn Fn(LogLevel, fmt::Arguments<'_>) + Send + Sync>;
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Sends all diagnostics of the net module to `logger`. Until one is set,
+/// errors and warnings go to stderr and everything else is dropped.
+pub fn set_logger(logger: impl Fn(LogLevel, fmt::Arguments<'_>) + Send + Sync + 'static) {
+    *LOGGER.write().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(logger));
+}
+
+pub(crate) fn log(level: LogLevel, args: fmt::Arguments<'_>) {
+    match &*LOGGER.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(logger) => logger(level, args),
+        None if level <= LogLevel::Warn => eprintln!("[{level:?}] {args}"),
+        None => (),
+    }
+}
+
+/// `net_log!(Warn, "format {}", args)`
+macro_rules! net_log {
+    ($level:ident, $($arg:tt)*) => {
+        $crate::net::log::log($crate::net::log::LogLevel::$level, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use net_log;
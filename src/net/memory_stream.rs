@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use super::transport::{unspecified_addr, Transport};
+
+/// Bytes travelling in one direction.
+#[derive(Default)]
+struct Pipe {
+    data: VecDeque<u8>,
+    /// No more bytes will be written.
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Channel {
+    pipe: Mutex<Pipe>,
+    readable: Condvar,
+}
+
+impl Channel {
+    fn pipe(&self) -> MutexGuard<'_, Pipe> {
+        self.pipe.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn close(&self) {
+        self.pipe().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// State shared by all clones of one end. Dropping the last clone closes the
+/// end, so the peer reads EOF just like with a socket.
+struct End {
+    incoming: Arc<Channel>,
+    outgoing: Arc<Channel>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+/// One end of an in-memory duplex connection, for testing protocol code
+/// without sockets. Create both ends with [`MemoryStream::pair`].
+///
+/// It behaves like a blocking `TcpStream`: reads wait for data and honour
+/// the read timeout, writes never block, and `shutdown` ends both directions.
+#[derive(Clone)]
+pub struct MemoryStream {
+    end: Arc<End>,
+}
+
+impl MemoryStream {
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = (Arc::new(Channel::default()), Arc::new(Channel::default()));
+        let end = |incoming, outgoing| Self { end: Arc::new(End { incoming, outgoing, read_timeout: Mutex::new(None) }) };
+        (end(a.clone(), b.clone()), end(b, a))
+    }
+
+    /// Number of written bytes the peer has not read yet.
+    pub fn unread(&self) -> usize {
+        self.end.outgoing.pipe().data.len()
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = *self.end.read_timeout.lock().unwrap_or_else(PoisonError::into_inner);
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let channel = &self.end.incoming;
+        let mut pipe = channel.pipe();
+        while pipe.data.is_empty() && !pipe.closed && !buf.is_empty() {
+            pipe = match deadline {
+                None => channel.readable.wait(pipe).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                    channel.readable.wait_timeout(pipe, deadline - now).unwrap_or_else(PoisonError::into_inner).0
+                }
+            };
+        }
+        let n = buf.len().min(pipe.data.len());
+        for (byte, value) in buf.iter_mut().zip(pipe.data.drain(..n)) {
+            *byte = value;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.end.outgoing.pipe();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.data.extend(buf);
+        self.end.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.end.incoming.close();
+        self.end.outgoing.close();
+        Ok(())
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(unspecified_addr())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "zero timeout"));
+        }
+        *self.end.read_timeout.lock().unwrap_or_else(PoisonError::into_inner) = timeout;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for MemoryStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryStream").field("unread", &self.unread()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn behaves_like_a_socket_pair() {
+        let (mut a, mut b) = MemoryStream::pair();
+        a.write_all(b"hello").unwrap();
+        assert_eq!(a.unread(), 5);
+        let mut buf = [0; 3];
+        assert_eq!(b.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+
+        b.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut rest = [0; 8];
+        assert_eq!(b.read(&mut rest).unwrap(), 2);
+        assert_eq!(b.read(&mut rest).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        // A reader blocked on another thread wakes up with the data.
+        let mut reader = b.try_clone().unwrap();
+        let waiting = std::thread::spawn(move || {
+            reader.set_read_timeout(None).unwrap();
+            let mut buf = [0; 8];
+            let n = reader.read(&mut buf).unwrap();
+            buf[..n].to_vec()
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        a.write_all(b"late").unwrap();
+        assert_eq!(waiting.join().unwrap(), b"late");
+
+        // Dropping every handle of one end reads as EOF on the other.
+        drop(a);
+        assert_eq!(b.read(&mut rest).unwrap(), 0);
+        assert_eq!(b.write(b"x").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{borrow::Cow, path::Path, sync::RwLock};
+
+/// Built-in extension table. The first entry for a MIME type is its
+/// preferred extension.
+const MIME_TYPES: &[(&str, &str)] = &[
+    // Text
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("md", "text/markdown"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("ics", "text/calendar"),
+    // Images
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    // Audio
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("aac", "audio/aac"),
+    ("m4a", "audio/mp4"),
+    ("weba", "audio/webm"),
+    // Video
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    ("avi", "video/x-msvideo"),
+    ("mkv", "video/x-matroska"),
+    // Applications and archives
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("bin", "application/octet-stream"),
+];
+
+/// Sent for files whose extension is unknown.
+pub const DEFAULT_MIME: &str = "application/octet-stream";
+
+static CUSTOM_TYPES: RwLock<Vec<(Cow<'static, str>, Cow<'static, str>)>> = RwLock::new(Vec::new());
+
+/// Adds or overrides the MIME type for `extension`. Registered entries take
+/// precedence over the built-in table. Both may be owned, e.g. read from
+/// configuration at runtime.
+pub fn register_mime(extension: impl Into<Cow<'static, str>>, mime: impl Into<Cow<'static, str>>) {
+    let extension = match extension.into() {
+        Cow::Borrowed(ext) => Cow::Borrowed(ext.trim_start_matches('.')),
+        Cow::Owned(ext) if ext.starts_with('.') => Cow::Owned(ext.trim_start_matches('.').to_string()),
+        ext => ext,
+    };
+    if let Ok(mut custom) = CUSTOM_TYPES.write() {
+        custom.retain(|(ext, _)| !ext.eq_ignore_ascii_case(&extension));
+        custom.push((extension, mime.into()));
+    }
+}
+
+pub fn mime_for_extension(extension: &str) -> Option<Cow<'static, str>> {
+    let extension = extension.trim_start_matches('.');
+    if let Ok(custom) = CUSTOM_TYPES.read()
+        && let Some((_, mime)) = custom.iter().find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+    {
+        return Some(mime.clone());
+    }
+    MIME_TYPES.iter().find(|(ext, _)| ext.eq_ignore_ascii_case(extension)).map(|(_, mime)| Cow::Borrowed(*mime))
+}
+
+/// MIME type for the extension of `path`, or [`DEFAULT_MIME`].
+pub fn mime_for_path(path: impl AsRef<Path>) -> Cow<'static, str> {
+    path.as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(mime_for_extension)
+        .unwrap_or(Cow::Borrowed(DEFAULT_MIME))
+}
+
+/// Preferred extension for `mime`, ignoring parameters like `charset`.
+pub fn extension_for_mime(mime: &str) -> Option<Cow<'static, str>> {
+    let mime = essence(mime);
+    if let Ok(custom) = CUSTOM_TYPES.read()
+        && let Some((ext, _)) = custom.iter().find(|(_, m)| m.eq_ignore_ascii_case(mime))
+    {
+        return Some(ext.clone());
+    }
+    MIME_TYPES.iter().find(|(_, m)| m.eq_ignore_ascii_case(mime)).map(|(ext, _)| Cow::Borrowed(*ext))
+}
+
+/// Whether `mime` is textual and should carry a charset.
+pub fn is_text_mime(mime: &str) -> bool {
+    let mime = essence(mime).to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(mime.as_str(), "application/json" | "application/xml" | "application/javascript")
+}
+
+/// `Content-Type` value for `mime` with `charset=UTF-8` added to text types.
+pub fn content_type_for_mime(mime: &str) -> String {
+    if is_text_mime(mime) && !mime.contains("charset") {
+        format!("{mime}; charset=UTF-8")
+    } else {
+        mime.to_string()
+    }
+}
+
+pub fn content_type_for_path(path: impl AsRef<Path>) -> String {
+    content_type_for_mime(&mime_for_path(path))
+}
+
+fn essence(mime: &str) -> &str {
+    mime.split(';').next().unwrap_or("").trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_types() {
+        assert_eq!(mime_for_path("a/b/style.CSS"), "text/css");
+        assert_eq!(mime_for_path("clip.mp4"), "video/mp4");
+        assert_eq!(mime_for_path("noext"), DEFAULT_MIME);
+        assert_eq!(extension_for_mime("image/jpeg; q=1").as_deref(), Some("jpg"));
+        assert_eq!(content_type_for_path("data.json"), "application/json; charset=UTF-8");
+        assert_eq!(content_type_for_path("logo.png"), "image/png");
+
+        register_mime("fef", "application/x-fef");
+        assert_eq!(mime_for_path("font/std.fef"), "application/x-fef");
+        assert_eq!(extension_for_mime("application/x-fef").as_deref(), Some("fef"));
+
+        // Pairs read at runtime are stored as they are.
+        let (extension, mime) = (String::from(".Conf"), String::from("text/x-conf"));
+        register_mime(extension, mime);
+        assert_eq!(mime_for_path("app.conf"), "text/x-conf");
+        assert_eq!(extension_for_mime("text/x-conf").as_deref(), Some("Conf"));
+    }
+}
@@ -1,13 +1,104 @@
+mod error;
+pub(crate) mod log;
+mod stream;
+mod transport;
+mod memory_stream;
+#[cfg(feature = "tls")]
+mod tls;
 mod web_socket;
+mod frame;
+mod permessage_deflate;
+#[cfg(unix)]
+mod reactor;
+#[cfg(unix)]
+mod hub;
 mod https;
 mod http_request;
+mod headers;
+mod router;
+mod server;
+mod response;
+mod static_files;
+mod date;
+pub mod mime;
+mod zip_stream;
+mod compression;
+mod url;
+mod client;
+mod form;
+mod body_reader;
 
+pub use error::Error;
+pub use error::Result;
+pub use log::LogLevel;
+pub use log::set_logger;
+pub use stream::Stream;
+pub use transport::Transport;
+pub use transport::unspecified_addr;
+pub use memory_stream::MemoryStream;
+#[cfg(feature = "tls")]
+pub use tls::TlsStream;
+#[cfg(feature = "tls")]
+pub use rustls;
 pub use web_socket::MessageDataType;
+pub use web_socket::Message;
 pub use web_socket::WebSocketInterface;
 pub use web_socket::WebSocket;
+pub use web_socket::Role;
+pub use frame::Frame;
+pub use frame::OpCode;
+pub use frame::CloseCode;
+pub use frame::FrameError;
+pub use permessage_deflate::DeflateConfig;
+#[cfg(unix)]
+pub use reactor::Reactor;
+#[cfg(unix)]
+pub use reactor::ConnectionId;
+#[cfg(unix)]
+pub use hub::Hub;
+#[cfg(unix)]
+pub use hub::SlowConsumerPolicy;
 pub use https::HTTPS;
 pub use http_request::HTTPRequest;
-pub use http_request::RequestType;
+pub use http_request::Method;
+pub use http_request::ParseError;
+pub use http_request::percent_decode;
+pub use http_request::parse_query;
+pub use headers::Headers;
+pub use router::Router;
+pub use router::RouteMatch;
+pub use router::Params;
+pub use router::Handler;
+pub use router::UpgradeHandler;
+pub use router::FormHandler;
+pub use server::Server;
+pub use server::ServerHandle;
+pub use response::Response;
+pub use response::Cookie;
+pub use response::SameSite;
+pub use response::reason_phrase;
+pub use response::Body;
+pub use response::ChunkedWriter;
+pub use static_files::StaticFiles;
+pub use date::http_date;
+pub use date::parse_http_date;
+pub use mime::mime_for_path;
+pub use mime::extension_for_mime;
+pub use zip_stream::ZipStreamWriter;
+pub use zip_stream::ZipOptions;
+pub use zip_stream::ZipMethod;
+pub use compression::ContentEncoding;
+pub use compression::CompressionConfig;
+pub use compression::is_compressible;
+pub use url::Url;
+pub use client::HttpClient;
+pub use client::RequestBuilder;
+pub use form::Form;
+pub use form::FormConfig;
+pub use form::FormError;
+pub use form::UploadedFile;
+pub use form::parse_urlencoded;
+pub use form::parse_multipart;
 
 mod tests {
 
@@ -15,4 +106,4 @@ mod tests {
     fn it_works() {
         println!("lol")
     }
-}
\ No newline at end of file
+}
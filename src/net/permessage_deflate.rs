@@ -0,0 +1,328 @@
+use std::io::{self, Write};
+
+use flate2::{write::DeflateEncoder, Compression, Decompress, FlushDecompress, Status};
+
+/// Settings for the `permessage-deflate` extension (RFC 7692).
+#[derive(Debug, Clone, Copy)]
+pub struct DeflateConfig {
+    pub level: u32,
+    /// Largest LZ77 window, as a power of two from 8 to 15, the peer is asked
+    /// to compress with. Smaller windows use less memory on the peer.
+    pub max_window_bits: u8,
+    /// Reset our compressor after every message instead of reusing its history.
+    pub no_context_takeover: bool,
+    /// Ask the peer to reset its compressor after every message.
+    pub peer_no_context_takeover: bool,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self { level: 6, max_window_bits: 15, no_context_takeover: false, peer_no_context_takeover: false }
+    }
+}
+
+impl DeflateConfig {
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+        self
+    }
+
+    pub fn max_window_bits(mut self, bits: u8) -> Self {
+        self.max_window_bits = bits.clamp(8, 15);
+        self
+    }
+
+    pub fn no_context_takeover(mut self, no_context_takeover: bool) -> Self {
+        self.no_context_takeover = no_context_takeover;
+        self
+    }
+
+    pub fn peer_no_context_takeover(mut self, no_context_takeover: bool) -> Self {
+        self.peer_no_context_takeover = no_context_takeover;
+        self
+    }
+}
+
+/// Parameters both sides agreed on, seen from one end of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Negotiated {
+    /// Window our compressor must stay within.
+    pub send_window_bits: u8,
+    pub send_no_context_takeover: bool,
+    pub receive_no_context_takeover: bool,
+}
+
+impl Negotiated {
+    pub fn into_parts(self, level: u32) -> (Deflater, Inflater) {
+        (Deflater::new(self, level), Inflater::new(self.receive_no_context_takeover))
+    }
+}
+
+const EXTENSION: &str = "permessage-deflate";
+
+/// Splits one extension offer into its name and `key[=value]` parameters.
+fn parse_extension(offer: &str) -> (&str, Vec<(&str, Option<&str>)>) {
+    let mut parts = offer.split(';').map(str::trim);
+    let name = parts.next().unwrap_or("");
+    let params = parts
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim().trim_matches('"'))),
+            None => (p, None),
+        })
+        .collect();
+    (name, params)
+}
+
+fn window_bits(value: Option<&str>) -> Option<u8> {
+    value?.parse().ok().filter(|bits| (8..=15).contains(bits))
+}
+
+/// Server side: picks the first acceptable `permessage-deflate` offer from a
+/// `Sec-WebSocket-Extensions` header and returns the response header value.
+pub(crate) fn accept_offer(header: &str, config: &DeflateConfig) -> Option<(String, Negotiated)> {
+    'offers: for offer in header.split(',') {
+        let (name, params) = parse_extension(offer);
+        if !name.eq_ignore_ascii_case(EXTENSION) {
+            continue;
+        }
+
+        let mut server_no_context_takeover = config.no_context_takeover;
+        let mut client_no_context_takeover = config.peer_no_context_takeover;
+        let mut server_window = None;
+        let mut client_window = None;
+        let mut seen = Vec::with_capacity(params.len());
+        for (key, value) in params {
+            if seen.contains(&key) {
+                continue 'offers;
+            }
+            seen.push(key);
+            match (key, value) {
+                ("server_no_context_takeover", None) => server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => client_no_context_takeover = true,
+                ("server_max_window_bits", value) => match window_bits(value) {
+                    Some(bits) => server_window = Some(bits),
+                    None => continue 'offers,
+                },
+                ("client_max_window_bits", None) => client_window = Some(15),
+                ("client_max_window_bits", value) => match window_bits(value) {
+                    Some(bits) => client_window = Some(bits),
+                    None => continue 'offers,
+                },
+                _ => continue 'offers,
+            }
+        }
+
+        let mut response = EXTENSION.to_string();
+        if server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+        if client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+        if let Some(bits) = server_window {
+            response.push_str(&format!("; server_max_window_bits={bits}"));
+        }
+        // The client's window can only be limited if it said it supports that.
+        if let Some(bits) = client_window.map(|bits| bits.min(config.max_window_bits))
+            && bits < 15
+        {
+            response.push_str(&format!("; client_max_window_bits={bits}"));
+        }
+
+        let negotiated = Negotiated {
+            send_window_bits: server_window.unwrap_or(15),
+            send_no_context_takeover: server_no_context_takeover,
+            receive_no_context_takeover: client_no_context_takeover,
+        };
+        return Some((response, negotiated));
+    }
+    None
+}
+
+/// Client side: the `Sec-WebSocket-Extensions` value offered in the handshake.
+pub(crate) fn client_offer(config: &DeflateConfig) -> String {
+    let mut offer = format!("{EXTENSION}; client_max_window_bits");
+    if config.max_window_bits < 15 {
+        offer.push_str(&format!("; server_max_window_bits={}", config.max_window_bits));
+    }
+    if config.no_context_takeover {
+        offer.push_str("; client_no_context_takeover");
+    }
+    if config.peer_no_context_takeover {
+        offer.push_str("; server_no_context_takeover");
+    }
+    offer
+}
+
+/// Client side: validates the server's answer to [`client_offer`]. `Ok(None)`
+/// means the server declined compression; an error fails the handshake.
+pub(crate) fn accept_response(header: Option<&str>, config: &DeflateConfig) -> Result<Option<Negotiated>, String> {
+    let Some(header) = header else {
+        return Ok(None);
+    };
+    let mut negotiated = None;
+    for extension in header.split(',') {
+        let (name, params) = parse_extension(extension);
+        if !name.eq_ignore_ascii_case(EXTENSION) || negotiated.is_some() {
+            return Err(format!("server accepted an extension that was not offered: `{}`", extension.trim()));
+        }
+
+        let mut result = Negotiated {
+            send_window_bits: 15,
+            send_no_context_takeover: config.no_context_takeover,
+            receive_no_context_takeover: false,
+        };
+        for (key, value) in params {
+            match (key, value) {
+                ("server_no_context_takeover", None) => result.receive_no_context_takeover = true,
+                ("client_no_context_takeover", None) => result.send_no_context_takeover = true,
+                ("server_max_window_bits", value) if window_bits(value).is_some() => (),
+                ("client_max_window_bits", value) => {
+                    result.send_window_bits = window_bits(value).ok_or("invalid client_max_window_bits")?;
+                }
+                _ => return Err(format!("unexpected permessage-deflate parameter `{key}`")),
+            }
+        }
+        negotiated = Some(result);
+    }
+    Ok(negotiated)
+}
+
+/// Compresses outgoing messages.
+#[derive(Debug)]
+pub(crate) struct Deflater {
+    encoder: DeflateEncoder<Vec<u8>>,
+    level: Compression,
+    window_bits: u8,
+    no_context_takeover: bool,
+}
+
+impl Deflater {
+    fn new(negotiated: Negotiated, level: u32) -> Self {
+        let level = Compression::new(level);
+        Self {
+            encoder: DeflateEncoder::new(Vec::new(), level),
+            level,
+            window_bits: negotiated.send_window_bits,
+            // The encoder always uses a 32K window, so a smaller negotiated one is
+            // met by never referring back past the start of the current message.
+            no_context_takeover: negotiated.send_no_context_takeover || negotiated.send_window_bits < 15,
+        }
+    }
+
+    /// Compresses one message. Returns `None` if it has to be sent as is
+    /// because it is larger than the negotiated window.
+    pub fn compress(&mut self, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if self.window_bits < 15 && data.len() > 1 << self.window_bits {
+            return Ok(None);
+        }
+        self.encoder.write_all(data)?;
+        self.encoder.flush()?;
+        let mut compressed = std::mem::take(self.encoder.get_mut());
+        // A sync flush ends with an empty stored block the receiver adds back.
+        if compressed.ends_with(&[0x00, 0x00, 0xFF, 0xFF]) {
+            compressed.truncate(compressed.len() - 4);
+        }
+        if self.no_context_takeover {
+            self.encoder = DeflateEncoder::new(Vec::new(), self.level);
+        }
+        Ok(Some(compressed))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum InflateError {
+    TooLarge,
+    Corrupt,
+}
+
+/// Decompresses incoming messages that had RSV1 set.
+#[derive(Debug)]
+pub(crate) struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+}
+
+impl Inflater {
+    fn new(no_context_takeover: bool) -> Self {
+        Self { decompress: Decompress::new(false), no_context_takeover }
+    }
+
+    /// Decompresses one whole message, giving up once the output exceeds `max_size`.
+    pub fn decompress(&mut self, mut data: Vec<u8>, max_size: u64) -> Result<Vec<u8>, InflateError> {
+        data.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+        let start = self.decompress.total_in();
+        let mut out = Vec::with_capacity(data.len() * 2);
+
+        loop {
+            if out.len() as u64 > max_size {
+                return Err(InflateError::TooLarge);
+            }
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity().max(1024));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let produced = out.len();
+            let status = self.decompress
+                .decompress_vec(&data[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|_| InflateError::Corrupt)?;
+            let done = (self.decompress.total_in() - start) as usize == data.len();
+            if done && out.len() < out.capacity() {
+                break;
+            }
+            let progressed = out.len() > produced || (self.decompress.total_in() - start) as usize > consumed;
+            if status == Status::StreamEnd || (!progressed && out.len() < out.capacity()) {
+                break;
+            }
+        }
+
+        if out.len() as u64 > max_size {
+            return Err(InflateError::TooLarge);
+        }
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_offers() {
+        let config = DeflateConfig::default().max_window_bits(10);
+        let (response, negotiated) = accept_offer(
+            "x-webkit-deflate-frame, permessage-deflate; foo, permessage-deflate; client_max_window_bits; server_max_window_bits=12",
+            &config,
+        ).unwrap();
+        assert_eq!(response, "permessage-deflate; server_max_window_bits=12; client_max_window_bits=10");
+        assert_eq!((negotiated.send_window_bits, negotiated.receive_no_context_takeover), (12, false));
+        assert!(accept_offer("permessage-deflate; server_max_window_bits=16", &config).is_none());
+
+        let client = accept_response(Some(&response), &config).unwrap().unwrap();
+        assert_eq!(client.send_window_bits, 10);
+        assert!(accept_response(Some("permessage-deflate; mystery"), &config).is_err());
+        assert_eq!(accept_response(None, &config), Ok(None));
+    }
+
+    #[test]
+    fn round_trips_messages_with_and_without_context() {
+        let negotiated = Negotiated { send_window_bits: 15, send_no_context_takeover: false, receive_no_context_takeover: false };
+        let (mut deflater, mut inflater) = negotiated.into_parts(6);
+        let message = b"{\"temperature\": 21.5, \"humidity\": 40}".repeat(20);
+        let first = deflater.compress(&message).unwrap().unwrap();
+        let second = deflater.compress(&message).unwrap().unwrap();
+        // The second copy refers back into the first one.
+        assert!(second.len() < first.len());
+        assert_eq!(inflater.decompress(first, 1 << 20).unwrap(), message);
+        assert_eq!(inflater.decompress(second, 1 << 20).unwrap(), message);
+        assert_eq!(inflater.decompress(vec![0xFF; 8], 1 << 20), Err(InflateError::Corrupt));
+
+        let (mut deflater, mut inflater) = negotiated.into_parts(6);
+        let bomb = deflater.compress(&vec![0; 1 << 20]).unwrap().unwrap();
+        assert_eq!(inflater.decompress(bomb, 1024), Err(InflateError::TooLarge));
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    sync::{atomic::{AtomicU64, Ordering}, mpsc},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use super::{
+    log::net_log,
+    web_socket::{deliver, fail, Incoming},
+    CloseCode, Error, Message, Result, WebSocketInterface,
+};
+
+/// A connection owned by a [`Reactor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+type Callback = Box<dyn FnOnce(&mut dyn WebSocketInterface) + Send>;
+
+enum Command {
+    Add(ConnectionId, Box<dyn WebSocketInterface + Send>),
+    With(ConnectionId, Callback),
+    Shutdown,
+}
+
+/// One event loop thread and the way to reach it.
+struct Worker {
+    commands: mpsc::Sender<Command>,
+    /// Writing a byte here wakes the thread out of `poll`.
+    wake: UnixStream,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands.send(command).map_err(|_| Error::Closed)?;
+        // A full wake buffer already guarantees a wakeup.
+        match (&self.wake).write(&[1]) {
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Runs many WebSocket connections on a few threads with non-blocking
+/// sockets and `poll(2)`, instead of one thread per connection.
+///
+/// Each connection lives on exactly one thread, which calls its
+/// [`WebSocketInterface`] methods directly, so no locking is involved.
+/// Other threads reach a connection through [`Reactor::with`].
+pub struct Reactor {
+    workers: Vec<Worker>,
+    next_id: AtomicU64,
+}
+
+impl Reactor {
+    pub fn new(threads: usize) -> Result<Self> {
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let (commands, receiver) = mpsc::channel();
+                let (wake, wake_receiver) = UnixStream::pair()?;
+                wake.set_nonblocking(true)?;
+                wake_receiver.set_nonblocking(true)?;
+                let thread = thread::Builder::new()
+                    .name("websocket-reactor".into())
+                    .spawn(move || EventLoop::new(receiver, wake_receiver).run())?;
+                Ok(Worker { commands, wake, thread: Some(thread) })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { workers, next_id: AtomicU64::new(0) })
+    }
+
+    /// Hands a connection to the reactor. Its socket is switched to
+    /// non-blocking mode; from now on events arrive on a reactor thread.
+    pub fn add(&self, client: impl WebSocketInterface + Send + 'static) -> Result<ConnectionId> {
+        let id = self.next_id();
+        self.insert(id, client)?;
+        Ok(id)
+    }
+
+    /// Reserves an id for [`Reactor::insert`], for wrappers that need to know
+    /// it before the connection is added.
+    pub(crate) fn next_id(&self) -> ConnectionId {
+        ConnectionId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub(crate) fn insert(&self, id: ConnectionId, mut client: impl WebSocketInterface + Send + 'static) -> Result<()> {
+        client.websocket_mut().stream().set_nonblocking(true)?;
+        self.worker(id).send(Command::Add(id, Box::new(client)))
+    }
+
+    /// Runs `f` with the connection on its reactor thread. Does nothing if
+    /// the connection is gone by then.
+    pub fn with<F>(&self, id: ConnectionId, f: F) -> Result<()>
+    where F: FnOnce(&mut dyn WebSocketInterface) + Send + 'static {
+        self.worker(id).send(Command::With(id, Box::new(f)))
+    }
+
+    pub fn send(&self, id: ConnectionId, message: Message) -> Result<()> {
+        self.with(id, move |client| {
+            if client.websocket_mut().send_message(message).is_err() {
+                client.websocket_mut().disconnect();
+            }
+        })
+    }
+
+    pub fn close(&self, id: ConnectionId, code: CloseCode, reason: &str) -> Result<()> {
+        let reason = reason.to_string();
+        self.with(id, move |client| {
+            if client.websocket_mut().close_with(code, &reason).is_err() {
+                client.websocket_mut().disconnect();
+            }
+        })
+    }
+
+    fn worker(&self, id: ConnectionId) -> &Worker {
+        &self.workers[(id.0 % self.workers.len() as u64) as usize]
+    }
+
+    /// Stops all threads. Open connections are dropped without a close handshake.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        for worker in &self.workers {
+            let _ = worker.send(Command::Shutdown);
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Connection {
+    id: ConnectionId,
+    client: Box<dyn WebSocketInterface + Send>,
+    incoming: Incoming,
+    ip: SocketAddr,
+    /// Set once `on_closed` ran; the connection is dropped after flushing.
+    finished: bool,
+}
+
+struct EventLoop {
+    commands: mpsc::Receiver<Command>,
+    wake: UnixStream,
+    connections: Vec<Connection>,
+    buffer: Box<[u8; 16384]>,
+}
+
+/// Longest `poll` sleeps without activity, so close timeouts are noticed.
+const POLL_INTERVAL_MS: i32 = 1000;
+
+/// Reads of `EventLoop::buffer` per readiness event and connection.
+const READS_PER_EVENT: usize = 4;
+
+impl EventLoop {
+    fn new(commands: mpsc::Receiver<Command>, wake: UnixStream) -> Self {
+        Self { commands, wake, connections: Vec::new(), buffer: Box::new([0; 16384]) }
+    }
+
+    fn run(mut self) {
+        let mut fds = Vec::new();
+        loop {
+            fds.clear();
+            fds.push(libc::pollfd { fd: self.wake.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+            for connection in &self.connections {
+                let ws = connection.client.websocket();
+                // A finished connection only waits for its close frame to go out.
+                let mut events = if connection.finished { 0 } else { libc::POLLIN };
+                if ws.has_pending() {
+                    events |= libc::POLLOUT;
+                }
+                fds.push(libc::pollfd { fd: ws.stream().as_raw_fd(), events, revents: 0 });
+            }
+
+            // Wake up in time for the earliest heartbeat.
+            let now = Instant::now();
+            let timeout = self.connections
+                .iter()
+                .filter_map(|c| c.client.websocket().next_tick())
+                .map(|at| at.saturating_duration_since(now).as_millis() as i32 + 1)
+                .fold(POLL_INTERVAL_MS, i32::min);
+            // Reads stop after `READS_PER_EVENT`, possibly with decrypted data left.
+            let buffered: Vec<bool> = self.connections
+                .iter()
+                .map(|c| !c.finished && c.client.websocket().stream().has_buffered_data())
+                .collect();
+            let timeout = if buffered.contains(&true) { 0 } else { timeout };
+
+            // SAFETY: `fds` is a valid, initialized slice of pollfd for the whole call.
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if ready < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    net_log!(Error, "reactor thread stopped, poll failed: {e}");
+                    return;
+                }
+            }
+
+            // Connections added by commands below were not polled yet.
+            let polled = self.connections.len();
+            for (index, fd) in fds[1..].iter().enumerate() {
+                if fd.revents != 0 || buffered[index] {
+                    self.read(index);
+                }
+            }
+            if fds[0].revents != 0 && !self.handle_commands() {
+                return;
+            }
+
+            for connection in &mut self.connections[..polled] {
+                if connection.finished {
+                    continue;
+                }
+                let ws = connection.client.websocket_mut();
+                if !ws.tick() {
+                    ws.disconnect();
+                    connection.client.on_closed(connection.ip, CloseCode::ABNORMAL, "heartbeat timed out");
+                    connection.finished = true;
+                } else if ws.close_timed_out() {
+                    connection.client.on_closed(connection.ip, CloseCode::ABNORMAL, "close handshake timed out");
+                    connection.finished = true;
+                }
+            }
+            self.flush();
+        }
+    }
+
+    /// Reads a few buffers' worth and delivers every complete event. What is
+    /// left waits for the next `poll`, so one busy peer can't hold up the
+    /// other connections on this thread.
+    fn read(&mut self, index: usize) {
+        let connection = &mut self.connections[index];
+        if connection.finished {
+            return;
+        }
+        for _ in 0..READS_PER_EVENT {
+            if connection.incoming.is_full() {
+                break;
+            }
+            let result = connection.client.websocket().stream().read(&mut self.buffer[..]);
+            match result {
+                Ok(0) => {
+                    connection.client.on_closed(connection.ip, CloseCode::ABNORMAL, "");
+                    connection.finished = true;
+                    return;
+                }
+                Ok(n) => connection.incoming.extend(&self.buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    net_log!(Debug, "WebSocket {}: read failed: {e}", connection.ip);
+                    connection.client.on_closed(connection.ip, CloseCode::ABNORMAL, "");
+                    connection.finished = true;
+                    return;
+                }
+            }
+        }
+
+        loop {
+            match connection.incoming.next_event() {
+                Ok(Some(event)) => {
+                    if !deliver(&mut *connection.client, event, connection.ip) {
+                        connection.finished = true;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(code) => {
+                    net_log!(Info, "WebSocket {}: closing connection: {code}", connection.ip);
+                    fail(&mut *connection.client, code, connection.ip);
+                    connection.finished = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns `false` when the loop should stop.
+    fn handle_commands(&mut self) -> bool {
+        while (&self.wake).read(&mut self.buffer[..]).is_ok_and(|n| n > 0) {}
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::Add(id, mut client) => {
+                    let ws = client.websocket_mut();
+                    let ip = match ws.stream().peer_addr() {
+                        Ok(ip) => ip,
+                        Err(e) => {
+                            net_log!(Debug, "reactor dropped connection {id}: {e}");
+                            continue;
+                        }
+                    };
+                    let incoming = Incoming::new(ws);
+                    self.connections.push(Connection { id, client, incoming, ip, finished: false });
+                    // Bytes that came with the handshake may already hold frames.
+                    let index = self.connections.len() - 1;
+                    self.deliver_buffered(index);
+                }
+                Command::With(id, f) => {
+                    if let Some(connection) = self.connections.iter_mut().find(|c| c.id == id && !c.finished) {
+                        f(&mut *connection.client);
+                    }
+                }
+                Command::Shutdown => return false,
+            }
+        }
+        true
+    }
+
+    fn deliver_buffered(&mut self, index: usize) {
+        let connection = &mut self.connections[index];
+        while let Ok(Some(event)) = connection.incoming.next_event() {
+            if !deliver(&mut *connection.client, event, connection.ip) {
+                connection.finished = true;
+                return;
+            }
+        }
+    }
+
+    /// Writes queued frames and drops connections that are done.
+    fn flush(&mut self) {
+        self.connections.retain_mut(|connection| {
+            let ws = connection.client.websocket_mut();
+            match ws.write_pending() {
+                Ok(true) => !connection.finished,
+                // Give a finished connection's close frame a chance to go out.
+                Ok(false) => !connection.finished || !ws.close_timed_out(),
+                Err(e) => {
+                    net_log!(Debug, "WebSocket {}: write failed: {e}", connection.ip);
+                    if !connection.finished {
+                        connection.client.on_closed(connection.ip, CloseCode::ABNORMAL, "");
+                    }
+                    false
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{Router, Server, WebSocket};
+    use std::sync::{mpsc::{channel, Sender}, Arc, Mutex, RwLock};
+
+    struct Echo {
+        ws: WebSocket,
+    }
+
+    impl WebSocketInterface for Echo {
+        fn on_message(&mut self, message: Message) {
+            self.ws.send_message(message).unwrap();
+        }
+        fn on_closed(&self, _ip: SocketAddr, _code: CloseCode, _reason: &str) {}
+        fn websocket(&self) -> &WebSocket {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket {
+            &mut self.ws
+        }
+    }
+
+    struct Client {
+        ws: WebSocket,
+        received: Sender<Message>,
+    }
+
+    impl WebSocketInterface for Client {
+        fn on_message(&mut self, message: Message) {
+            let _ = self.received.send(message);
+            self.ws.close().unwrap();
+        }
+        fn on_closed(&self, _ip: SocketAddr, _code: CloseCode, _reason: &str) {}
+        fn websocket(&self) -> &WebSocket {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket {
+            &mut self.ws
+        }
+    }
+
+    #[test]
+    fn serves_many_connections_on_one_thread() {
+        let reactor = Arc::new(Reactor::new(1).unwrap());
+        let (ids, added) = channel();
+        let ids = Mutex::new(ids);
+        let mut router = Router::new();
+        let shared = reactor.clone();
+        router.websocket("/echo", move |ws, _, _| {
+            let id = shared.add(Echo { ws }).unwrap();
+            let _ = ids.lock().unwrap().send(id);
+        });
+        let server = Server::bind("127.0.0.1:0", router).unwrap().workers(2).spawn().unwrap();
+        let url = format!("ws://{}/echo", server.local_addr());
+
+        let clients: Vec<_> = (0..20)
+            .map(|i| {
+                let url = url.clone();
+                thread::spawn(move || {
+                    let mut ws = WebSocket::connect(&url).unwrap();
+                    ws.send_message(Message::Text(format!("client {i}"))).unwrap();
+                    let (sender, received) = channel();
+                    WebSocket::run(Arc::new(RwLock::new(Client { ws, received: sender }))).unwrap();
+                    received.recv().unwrap()
+                })
+            })
+            .collect();
+        for (i, client) in clients.into_iter().enumerate() {
+            assert_eq!(client.join().unwrap(), Message::Text(format!("client {i}")));
+        }
+
+        // Messages can also be pushed from outside the reactor thread.
+        let ws = WebSocket::connect(&url).unwrap();
+        let id = (0..21).map(|_| added.recv().unwrap()).max().unwrap();
+        reactor.send(id, Message::Binary(vec![1, 2, 3])).unwrap();
+        let (sender, received) = channel();
+        WebSocket::run(Arc::new(RwLock::new(Client { ws, received: sender }))).unwrap();
+        assert_eq!(received.recv().unwrap(), Message::Binary(vec![1, 2, 3]));
+        server.shutdown();
+    }
+}
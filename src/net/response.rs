@@ -0,0 +1,514 @@
+use std::{borrow::Cow, fmt, io::{self, Read, Write}};
+
+use super::{headers::strip_line_breaks, http_request::{find_head_end, parse_headers, MAX_HEAD_LEN}, Headers, ParseError};
+
+/// Callback behind [`Body::Stream`].
+pub type BodyWriter = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// Payload of a [`Response`].
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Streamed from `reader` while writing. With a known `len` it is sent with
+    /// `Content-Length`, otherwise with chunked transfer encoding.
+    Reader { reader: Box<dyn Read + Send>, len: Option<u64> },
+    /// Produced by a callback that writes into the connection; always chunked.
+    Stream(BodyWriter),
+}
+
+impl Body {
+    pub fn reader(reader: impl Read + Send + 'static, len: Option<u64>) -> Self {
+        Self::Reader { reader: Box::new(reader), len }
+    }
+
+    pub fn stream(writer: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static) -> Self {
+        Self::Stream(Box::new(writer))
+    }
+
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes.len() as u64),
+            Self::Reader { len, .. } => *len,
+            Self::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The payload if it is already in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            Self::Reader { .. } | Self::Stream(_) => None,
+        }
+    }
+
+    /// Collects the payload, draining a reader body.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Bytes(bytes) => Ok(bytes),
+            Self::Reader { reader, len: Some(len) } => {
+                let mut bytes = Vec::with_capacity(len as usize);
+                reader.take(len).read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Self::Reader { mut reader, len: None } => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Self::Stream(writer) => {
+                let mut bytes = Vec::new();
+                writer(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::Bytes(Vec::new())
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Self::Reader { len, .. } => write!(f, "Reader {{ len: {len:?} }}"),
+            Self::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Self::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Self::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Self::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+/// Wraps a writer and frames everything written to it as HTTP chunks.
+/// [`ChunkedWriter::finish`] must be called to send the terminating chunk.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An HTTP/1.1 response with status, header fields and body.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub reason: Cow<'static, str>,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+impl Response {
+    /// Empty response with the standard reason phrase for `status`.
+    pub fn new(status: u16) -> Self {
+        Self { status, reason: Cow::Borrowed(reason_phrase(status)), headers: Headers::new(), body: Body::default() }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(200)
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(404)
+    }
+
+    pub fn bad_request() -> Self {
+        Self::new(400)
+    }
+
+    pub fn internal_error() -> Self {
+        Self::new(500)
+    }
+
+    pub fn not_modified() -> Self {
+        Self::new(304)
+    }
+
+    /// Redirect with `status` which should be one of 301, 302, 303, 307 or 308.
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Self::new(status).header("Location", location)
+    }
+
+    /// `303 See Other`, the usual answer to a successful form POST.
+    pub fn see_other(location: &str) -> Self {
+        Self::redirect(303, location)
+    }
+
+    pub fn text(body: impl Into<String>) -> Self {
+        Self::ok().content_type("text/plain; charset=UTF-8").body(body.into())
+    }
+
+    pub fn html(body: impl Into<String>) -> Self {
+        Self::ok().content_type("text/html; charset=UTF-8").body(body.into())
+    }
+
+    /// Changes the status code together with its standard reason phrase.
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self.reason = Cow::Borrowed(reason_phrase(status));
+        self
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<Cow<'static, str>>) -> Self {
+        self.reason = reason.into();
+        self
+    }
+
+    /// Sets `name`, replacing previous values.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    /// Adds `name` without replacing previous values.
+    pub fn append_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn content_type(self, content_type: &str) -> Self {
+        self.header("Content-Type", content_type)
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn cookie(self, cookie: Cookie) -> Self {
+        self.append_header("Set-Cookie", cookie.to_string())
+    }
+
+    /// Parses a status line and header fields from the start of `buf`.
+    ///
+    /// Returns `Ok(None)` while the head is incomplete, otherwise the response
+    /// with an empty body and the offset at which the body starts. Heads
+    /// longer than 64 KiB are refused.
+    pub fn parse_head(buf: &[u8]) -> Result<Option<(Self, usize)>, ParseError> {
+        let (head_len, body_start) = match find_head_end(buf) {
+            Some(end) => end,
+            None if buf.len() > MAX_HEAD_LEN => return Err(ParseError::TooLarge),
+            None => return Ok(None),
+        };
+        if head_len > MAX_HEAD_LEN {
+            return Err(ParseError::TooLarge);
+        }
+        let head = std::str::from_utf8(&buf[..head_len]).map_err(|_| ParseError::InvalidEncoding)?;
+        let mut lines = head.lines();
+
+        let status_line = lines.next().ok_or(ParseError::InvalidStatusLine)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or("");
+        if !version.starts_with("HTTP/1.") {
+            return Err(ParseError::UnsupportedVersion(version.to_string()));
+        }
+        let status = parts
+            .next()
+            .filter(|code| code.len() == 3)
+            .and_then(|code| code.parse().ok())
+            .ok_or(ParseError::InvalidStatusLine)?;
+        let reason = parts.next().unwrap_or("").to_string();
+
+        let response = Self { status, reason: Cow::Owned(reason), headers: parse_headers(lines)?, body: Body::default() };
+        Ok(Some((response, body_start)))
+    }
+
+    /// Whether the status forbids a message body (1xx, 204 and 304).
+    pub fn is_bodyless(&self) -> bool {
+        (100..200).contains(&self.status) || self.status == 204 || self.status == 304
+    }
+
+    /// Writes the status line and header fields including the blank line.
+    pub fn write_head(&self, writer: &mut impl Write) -> io::Result<()> {
+        // `reason` is a public field, so it is cleaned here rather than when set.
+        write!(writer, "HTTP/1.1 {} {}\r\n{}", self.status, strip_line_breaks(&self.reason), self.headers)?;
+        if !self.is_bodyless() && !self.headers.contains("Content-Length") && !self.headers.contains("Transfer-Encoding") {
+            match self.body.len() {
+                Some(len) => write!(writer, "Content-Length: {len}\r\n")?,
+                None => writer.write_all(b"Transfer-Encoding: chunked\r\n")?,
+            }
+        }
+        writer.write_all(b"\r\n")
+    }
+
+    pub fn write_to(self, writer: &mut impl Write) -> io::Result<()> {
+        self.write_head(writer)?;
+        if self.is_bodyless() {
+            return Ok(());
+        }
+        match self.body {
+            Body::Bytes(bytes) => writer.write_all(&bytes),
+            Body::Reader { reader, len: Some(len) } => {
+                let copied = io::copy(&mut reader.take(len), writer)?;
+                if copied < len {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "response body ended early"));
+                }
+                Ok(())
+            }
+            Body::Reader { mut reader, len: None } => {
+                let mut chunked = ChunkedWriter::new(writer);
+                io::copy(&mut reader, &mut chunked)?;
+                chunked.finish().map(|_| ())
+            }
+            Body::Stream(body) => {
+                // Buffer small writes so chunks are not a few bytes each.
+                let mut chunked = io::BufWriter::with_capacity(16 * 1024, ChunkedWriter::new(writer));
+                body(&mut chunked)?;
+                chunked.into_inner().map_err(|e| e.into_error())?.finish().map(|_| ())
+            }
+        }
+    }
+
+    pub fn to_bytes(self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.body.len().unwrap_or(0) as usize + 128);
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A `Set-Cookie` value with its attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<i64>,
+    /// Preformatted HTTP date, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`.
+    pub expires: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that tells the client to delete `name`.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Self::new(name, "").max_age(0)
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, seconds: i64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn expires(mut self, date: impl Into<String>) -> Self {
+        self.expires = Some(date.into());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={max_age}")?;
+        }
+        if let Some(expires) = &self.expires {
+            write!(f, "; Expires={expires}")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax"),
+            Some(SameSite::None) => f.write_str("; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_status_headers_and_cookies() {
+        let response = Response::redirect(302, "/login")
+            .cookie(Cookie::new("session", "abc").path("/").http_only(true).same_site(SameSite::Lax))
+            .cookie(Cookie::removal("old"));
+        let text = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "HTTP/1.1 302 Found\r\nLocation: /login\r\nSet-Cookie: session=abc; Path=/; HttpOnly; SameSite=Lax\r\n\
+             Set-Cookie: old=; Max-Age=0\r\nContent-Length: 0\r\n\r\n"
+        );
+
+        let text = String::from_utf8(Response::not_modified().header("ETag", "\"1\"").to_bytes().unwrap()).unwrap();
+        assert_eq!(text, "HTTP/1.1 304 Not Modified\r\nETag: \"1\"\r\n\r\n");
+    }
+
+    #[test]
+    fn drops_line_breaks_from_header_fields() {
+        let response = Response::redirect(302, "/next\r\nSet-Cookie: admin=1")
+            .header("X-Name\n", "a\0b")
+            .cookie(Cookie::new("id", "1\r\n\r\n<script>"));
+        let text = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "HTTP/1.1 302 Found\r\nLocation: /nextSet-Cookie: admin=1\r\nX-Name: ab\r\n\
+             Set-Cookie: id=1<script>\r\nContent-Length: 0\r\n\r\n"
+        );
+
+        let response = Response::ok().with_reason("OK\r\nSet-Cookie: x=1\r\n");
+        let text = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+        assert_eq!(text, "HTTP/1.1 200 OKSet-Cookie: x=1\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn streams_unknown_length_as_chunks() {
+        let response = Response::ok().body(Body::reader(&b"hello world"[..], None));
+        let text = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+        assert!(text.contains("Transfer-Encoding: chunked\r\n\r\n"));
+        assert!(text.ends_with("b\r\nhello world\r\n0\r\n\r\n"));
+    }
+}
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+
+use super::{Form, FormConfig, HTTPRequest, Method, Response, StaticFiles, WebSocket};
+
+pub type Handler = Arc<dyn Fn(&HTTPRequest, &Params) -> Response + Send + Sync>;
+pub type FormHandler = Arc<dyn Fn(&HTTPRequest, Form, &Params) -> Response + Send + Sync>;
+pub type UpgradeHandler = Arc<dyn Fn(WebSocket, &HTTPRequest, &Params) + Send + Sync>;
+
+/// Values captured by `:name` and `*name` segments of a route pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Static(String),
+    Param(String),
+    /// Swallows the rest of the path, e.g. `/files/*path`.
+    Wildcard(String),
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        let segments = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                if let Some(name) = s.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = s.strip_prefix('*') {
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Static(s.to_string())
+                }
+            })
+            .collect();
+        Self { segments }
+    }
+
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = path.split('/').filter(|s| !s.is_empty());
+        let mut params = Params::default();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(expected) => {
+                    if parts.next()? != expected {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => params.pairs.push((name.clone(), parts.next()?.to_string())),
+                Segment::Wildcard(name) => {
+                    let rest: Vec<&str> = parts.by_ref().collect();
+                    params.pairs.push((name.clone(), rest.join("/")));
+                }
+            }
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Handler,
+}
+
+struct FormRoute {
+    method: Method,
+    pattern: Pattern,
+    config: FormConfig,
+    handler: FormHandler,
+}
+
+/// Dispatches parsed requests to handlers by method and path pattern.
+///
+/// Routes are tried in the order they were added.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    forms: Vec<FormRoute>,
+    upgrades: Vec<(Pattern, UpgradeHandler)>,
+    fallback: Option<Handler>,
+}
+
+pub enum RouteMatch {
+    Found(Handler, Params),
+    /// The path exists but not for this method.
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> Response + Send + Sync + 'static {
+        self.routes.push(Route { method, pattern: Pattern::parse(pattern), handler: Arc::new(handler) });
+        self
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> Response + Send + Sync + 'static {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> Response + Send + Sync + 'static {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    pub fn put<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> Response + Send + Sync + 'static {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    pub fn delete<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> Response + Send + Sync + 'static {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Serves the files below `files` under `prefix`, e.g. `/assets`.
+    pub fn serve_dir(&mut self, prefix: &str, files: StaticFiles) -> &mut Self {
+        let pattern = format!("{}/*path", prefix.trim_end_matches('/'));
+        self.get(&pattern, move |request, params| files.serve_path(request, params.get("path").unwrap_or("")))
+    }
+
+    /// Decodes the form body before calling `handler`. `Server` streams file
+    /// parts straight to `config.upload_dir` instead of buffering the body, so
+    /// only the limits in `config` bound an upload. Failures are answered
+    /// without calling the handler, e.g. with 413 for a file over the limit.
+    pub fn form<F>(&mut self, method: Method, pattern: &str, config: FormConfig, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, Form, &Params) -> Response + Send + Sync + 'static {
+        self.forms.push(FormRoute { method, pattern: Pattern::parse(pattern), config, handler: Arc::new(handler) });
+        self
+    }
+
+    /// Accepts WebSocket upgrades on `pattern`. The handler runs on its own
+    /// thread, so it may block in `WebSocket::run`.
+    pub fn websocket<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(WebSocket, &HTTPRequest, &Params) + Send + Sync + 'static {
+        self.upgrades.push((Pattern::parse(pattern), Arc::new(handler)));
+        self
+    }
+
+    /// Handler for requests no route matches, instead of the default 404.
+    pub fn fallback<F>(&mut self, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> Response + Send + Sync + 'static {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn find(&self, method: Method, path: &str) -> RouteMatch {
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            if let Some(params) = route.pattern.matches(path) {
+                if route.method == method || (method == Method::HEAD && route.method == Method::GET) {
+                    return RouteMatch::Found(route.handler.clone(), params);
+                }
+                allowed.push(route.method);
+            }
+        }
+        allowed.extend(self.forms.iter().filter(|route| route.pattern.matches(path).is_some()).map(|route| route.method));
+
+        if let Some(fallback) = &self.fallback {
+            RouteMatch::Found(fallback.clone(), Params::default())
+        } else if allowed.is_empty() {
+            RouteMatch::NotFound
+        } else {
+            RouteMatch::MethodNotAllowed(allowed)
+        }
+    }
+
+    pub fn find_form(&self, method: Method, path: &str) -> Option<(FormHandler, &FormConfig, Params)> {
+        self.forms
+            .iter()
+            .filter(|route| route.method == method)
+            .find_map(|route| Some((route.handler.clone(), &route.config, route.pattern.matches(path)?)))
+    }
+
+    pub fn find_upgrade(&self, path: &str) -> Option<(UpgradeHandler, Params)> {
+        self.upgrades.iter().find_map(|(pattern, handler)| Some((handler.clone(), pattern.matches(path)?)))
+    }
+
+    /// Runs the matching handler or produces a 404/405 response.
+    pub fn handle(&self, request: &HTTPRequest) -> Response {
+        if let Some((handler, config, params)) = self.find_form(request.method, &request.path) {
+            return match request.form(config) {
+                Ok(form) => handler(request, form, &params),
+                Err(e) => Response::text(e.to_string()).with_status(e.status()),
+            };
+        }
+        match self.find(request.method, &request.path) {
+            RouteMatch::Found(handler, params) => handler(request, &params),
+            RouteMatch::MethodNotAllowed(allowed) => {
+                let allowed: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+                Response::new(405).header("Allow", allowed.join(", "))
+            }
+            RouteMatch::NotFound => Response::not_found(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_params_and_wildcards() {
+        let pattern = Pattern::parse("/users/:id/files/*path");
+        let params = pattern.matches("/users/42/files/a/b.txt").unwrap();
+        assert_eq!(params.get("id"), Some("42"));
+        assert_eq!(params.get("path"), Some("a/b.txt"));
+        assert!(pattern.matches("/users/42").is_none());
+        assert!(Pattern::parse("/users/:id").matches("/users/42/extra").is_none());
+    }
+}
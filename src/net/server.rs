@@ -0,0 +1,571 @@
+use std::{
+    io::{self, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{body_reader::BodyReader, form::read_form, http_request::MAX_HEAD_LEN, log::net_log, CompressionConfig, DeflateConfig, FormConfig, FormHandler, HTTPRequest, Method, ParseError, Params, Response, Result, Router, Stream, WebSocket};
+
+/// Per-connection settings shared by all workers.
+#[derive(Debug, Clone, Copy)]
+struct ConnectionConfig {
+    read_timeout: Duration,
+    idle_timeout: Duration,
+    max_requests: usize,
+    max_head_size: usize,
+    max_body_size: usize,
+    max_websockets: usize,
+    compression: Option<CompressionConfig>,
+    websocket_deflate: Option<DeflateConfig>,
+}
+
+/// Blocking HTTP/1.1 server that dispatches accepted connections to a fixed
+/// pool of worker threads.
+///
+/// Connections are kept alive between requests, so an idle client occupies a
+/// worker until `keep_alive` expires. Size the pool accordingly.
+pub struct Server {
+    listener: TcpListener,
+    router: Arc<Router>,
+    workers: usize,
+    config: ConnectionConfig,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, router: Router) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let workers = thread::available_parallelism().map_or(4, |n| n.get());
+        let config = ConnectionConfig {
+            read_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+            max_head_size: MAX_HEAD_LEN,
+            max_body_size: 16 * 1024 * 1024,
+            max_websockets: 1024,
+            compression: None,
+            websocket_deflate: Some(DeflateConfig::default()),
+        };
+        Ok(Self {
+            listener,
+            router: Arc::new(router),
+            workers,
+            config,
+            #[cfg(feature = "tls")]
+            tls: None,
+        })
+    }
+
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// How long a client may take to send a complete request.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// How long an idle persistent connection waits for its next request.
+    /// `None` closes every connection after one response.
+    pub fn keep_alive(mut self, idle_timeout: Option<Duration>) -> Self {
+        match idle_timeout {
+            Some(timeout) => self.config.idle_timeout = timeout,
+            None => self.config.max_requests = 1,
+        }
+        self
+    }
+
+    /// Closes a persistent connection after this many requests.
+    pub fn max_requests(mut self, max_requests: usize) -> Self {
+        self.config.max_requests = max_requests.max(1);
+        self
+    }
+
+    /// Largest request line plus headers, 64 KiB by default. Bigger heads are
+    /// answered with 431.
+    pub fn max_head_size(mut self, max_head_size: usize) -> Self {
+        self.config.max_head_size = max_head_size;
+        self
+    }
+
+    /// Largest request body, 16 MiB by default. Bigger bodies are answered
+    /// with 413 as soon as `Content-Length` or a chunk size gives them away.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.config.max_body_size = max_body_size;
+        self
+    }
+
+    /// Most WebSocket handlers running at once, 1024 by default. Each runs on
+    /// its own thread, so a handler that hands its socket to a `Reactor` and
+    /// returns frees its slot. Upgrades beyond the limit are answered with 503.
+    pub fn max_websockets(mut self, max_websockets: usize) -> Self {
+        self.config.max_websockets = max_websockets;
+        self
+    }
+
+    /// Compresses text responses for clients that send `Accept-Encoding`.
+    pub fn compression(mut self, config: CompressionConfig) -> Self {
+        self.config.compression = Some(config);
+        self
+    }
+
+    /// `permessage-deflate` settings for WebSocket upgrades, on by default.
+    /// `None` declines the extension.
+    pub fn websocket_deflate(mut self, config: Option<DeflateConfig>) -> Self {
+        self.config.websocket_deflate = config;
+        self
+    }
+
+    /// Serves HTTPS and `wss://` instead of plain HTTP.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections on the current thread until the listener fails.
+    pub fn run(self) -> Result<()> {
+        self.serve(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Runs the accept loop on a background thread.
+    pub fn spawn(self) -> Result<ServerHandle> {
+        let addr = self.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            if let Err(e) = self.serve(thread_stop) {
+                net_log!(Error, "server stopped accepting connections: {e}");
+            }
+        });
+        Ok(ServerHandle { addr, stop, thread })
+    }
+
+    fn serve(self, stop: Arc<AtomicBool>) -> Result<()> {
+        let (sender, receiver) = mpsc::channel::<TcpStream>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let websockets = Arc::new(AtomicUsize::new(0));
+
+        let workers: Vec<JoinHandle<()>> = (0..self.workers)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let router = self.router.clone();
+                let config = self.config;
+                let websockets = websockets.clone();
+                #[cfg(feature = "tls")]
+                let tls = self.tls.clone();
+                thread::spawn(move || loop {
+                    let stream = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+                    match stream {
+                        #[cfg(feature = "tls")]
+                        Ok(stream) if let Some(tls) = &tls => match super::TlsStream::accept(stream, tls.clone()) {
+                            Ok(stream) => handle_connection(stream.into(), &router, &config, &websockets),
+                            Err(e) => net_log!(Debug, "TLS setup failed: {e}"),
+                        },
+                        Ok(stream) => handle_connection(stream.into(), &router, &config, &websockets),
+                        Err(_) => return,
+                    }
+                })
+            })
+            .collect();
+
+        for stream in self.listener.incoming() {
+            if stop.load(Ordering::Acquire) {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted || e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        drop(sender);
+        for worker in workers {
+            let _ = worker.join();
+        }
+        Ok(())
+    }
+}
+
+pub struct ServerHandle {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting connections and waits for in-flight requests.
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::Release);
+        // Wake the blocking accept call.
+        let _ = TcpStream::connect(self.addr);
+        let _ = self.thread.join();
+    }
+}
+
+/// Serves requests on one connection until either side wants to close it.
+/// Pipelined requests are answered strictly in the order they arrived.
+/// `websockets` counts the upgraded connections whose handler still runs.
+fn handle_connection(mut stream: Stream, router: &Router, config: &ConnectionConfig, websockets: &Arc<AtomicUsize>) {
+    let mut buffer = Vec::with_capacity(4096);
+    let mut chunk = [0; 4096];
+    let mut served = 0;
+
+    loop {
+        let (mut request, body_start) = match HTTPRequest::parse_head(&buffer, config.max_head_size) {
+            Ok(Some(head)) => head,
+            Ok(None) => {
+                // Between requests the idle timeout applies, within one the read timeout.
+                let timeout = if buffer.is_empty() && served > 0 { config.idle_timeout } else { config.read_timeout };
+                let _ = stream.set_read_timeout(Some(timeout));
+                match stream.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) if buffer.is_empty() || !is_timeout(&e) => return,
+                    Err(_) => {
+                        let _ = Response::new(408).header("Connection", "close").write_to(&mut stream);
+                        return;
+                    }
+                }
+                continue;
+            }
+            Err(ParseError::TooLarge) => {
+                let _ = Response::new(431).header("Connection", "close").write_to(&mut stream);
+                return;
+            }
+            Err(_) => {
+                let _ = Response::bad_request().header("Connection", "close").write_to(&mut stream);
+                return;
+            }
+        };
+        buffer.drain(..body_start);
+
+        // Form routes read their body from the socket themselves.
+        if let Some((handler, form_config, params)) = router.find_form(request.method, &request.path) {
+            serve_form(stream, request, buffer, handler, form_config, params, config);
+            return;
+        }
+
+        let _ = stream.set_read_timeout(Some(config.read_timeout));
+        request.body = match read_body(&request, &mut buffer, &mut stream, config.max_body_size) {
+            Ok(body) => body,
+            Err(e) => {
+                let status = match e.kind() {
+                    io::ErrorKind::FileTooLarge => 413,
+                    io::ErrorKind::InvalidData => 400,
+                    _ if is_timeout(&e) => 408,
+                    // The client went away.
+                    _ => return,
+                };
+                let _ = Response::new(status).header("Connection", "close").write_to(&mut stream);
+                return;
+            }
+        };
+        served += 1;
+
+        if let Some(key) = request.websocket_key()
+            && let Some((handler, params)) = router.find_upgrade(&request.path)
+        {
+            let Some(slot) = UpgradeSlot::acquire(websockets, config.max_websockets) else {
+                net_log!(Warn, "refused WebSocket upgrade on {}: {} connections open", request.path, config.max_websockets);
+                let _ = Response::new(503).header("Connection", "close").write_to(&mut stream);
+                return;
+            };
+            let key = key.to_string();
+            let deflate = config.websocket_deflate;
+            thread::spawn(move || {
+                let _slot = slot;
+                let extensions = request.headers.get("Sec-WebSocket-Extensions");
+                match WebSocket::try_connect_with(stream, &key, extensions, deflate.as_ref()) {
+                    Ok(ws) => handler(ws, &request, &params),
+                    Err(e) => net_log!(Debug, "WebSocket handshake on {} failed: {e}", request.path),
+                }
+            });
+            return;
+        }
+
+        // A panicking handler costs the client a 500, not the server a worker.
+        let mut response = match panic::catch_unwind(AssertUnwindSafe(|| router.handle(&request))) {
+            Ok(response) => response,
+            Err(_) => {
+                net_log!(Error, "handler for {} panicked", request.path);
+                Response::internal_error().header("Connection", "close")
+            }
+        };
+        if let Some(compression) = &config.compression {
+            response = response.compress(&request, compression);
+        }
+
+        let keep_alive = request.keep_alive()
+            && served < config.max_requests
+            && !response.headers.has_token("Connection", "close");
+        if keep_alive {
+            response.headers.set("Connection", "keep-alive");
+            let timeout = config.idle_timeout.as_secs();
+            let remaining = config.max_requests - served;
+            response.headers.set("Keep-Alive", format!("timeout={timeout}, max={remaining}"));
+        } else {
+            response.headers.set("Connection", "close");
+        }
+
+        let written = match request.method {
+            Method::HEAD => response.write_head(&mut stream),
+            _ => response.write_to(&mut stream),
+        };
+        if written.and_then(|_| stream.flush()).is_err() || !keep_alive {
+            return;
+        }
+    }
+}
+
+/// Reads the body of `request` from the bytes in `buffer` and then from
+/// `stream`. Whatever arrived after the body stays in `buffer`.
+/// One of the `max_websockets` handler threads, released on drop.
+struct UpgradeSlot(Arc<AtomicUsize>);
+
+impl UpgradeSlot {
+    fn acquire(count: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        count.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1)).ok()?;
+        Some(Self(count.clone()))
+    }
+}
+
+impl Drop for UpgradeSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn read_body(request: &HTTPRequest, buffer: &mut Vec<u8>, stream: &mut Stream, max_len: usize) -> io::Result<Vec<u8>> {
+    let inner = io::Cursor::new(std::mem::take(buffer)).chain(stream);
+    let mut reader = BodyReader::new(request, inner, max_len).map_err(|e| match e {
+        ParseError::BodyTooLarge => io::Error::new(io::ErrorKind::FileTooLarge, e),
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    })?;
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body)?;
+
+    let (ahead, inner) = reader.into_parts();
+    let (rest, _) = inner.into_inner();
+    *buffer = ahead;
+    buffer.extend_from_slice(&rest.get_ref()[rest.position() as usize..]);
+    Ok(body)
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Answers a request to a form route, streaming its body from `stream`
+/// behind the already buffered `rest`. The connection is closed afterwards,
+/// as the body may not have been read to its end.
+fn serve_form(
+    mut stream: Stream,
+    request: HTTPRequest,
+    rest: Vec<u8>,
+    handler: FormHandler,
+    form_config: &FormConfig,
+    params: Params,
+    config: &ConnectionConfig,
+) {
+    if request.headers.get("Expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+        let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+    let _ = stream.set_read_timeout(Some(config.read_timeout));
+
+    let form = match BodyReader::new(&request, io::Cursor::new(rest).chain(&mut stream), usize::MAX) {
+        Ok(body) => read_form(&request, body, form_config, config.max_body_size),
+        Err(_) => {
+            let _ = Response::bad_request().header("Connection", "close").write_to(&mut stream);
+            return;
+        }
+    };
+    let mut response = match form {
+        Ok(form) => match panic::catch_unwind(AssertUnwindSafe(|| handler(&request, form, &params))) {
+            Ok(response) => response,
+            Err(_) => {
+                net_log!(Error, "handler for {} panicked", request.path);
+                Response::internal_error()
+            }
+        },
+        Err(e) => {
+            net_log!(Debug, "form on {} rejected: {e}", request.path);
+            Response::text(e.to_string()).with_status(e.status())
+        }
+    };
+    if let Some(compression) = &config.compression {
+        response = response.compress(&request, compression);
+    }
+    response.headers.set("Connection", "close");
+    if response.write_to(&mut stream).and_then(|_| stream.flush()).is_ok() {
+        linger(&mut stream);
+    }
+}
+
+/// Closes the sending side and discards what the client still sends for a
+/// moment, so the response isn't lost to a reset caused by unread data.
+fn linger(stream: &mut Stream) {
+    let _ = stream.shutdown(std::net::Shutdown::Write);
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(500)));
+    let _ = io::copy(&mut stream.take(64 << 20), &mut io::sink());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(addr: SocketAddr, raw: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn routes_requests_on_loopback() {
+        let mut router = Router::new();
+        router.get("/users/:id", |_, params| Response::text(format!("user {}", params.get("id").unwrap())));
+        router.route(Method::POST, "/echo", |request, _| Response::ok().body(request.body.clone()));
+
+        let server = Server::bind("127.0.0.1:0", router).unwrap().workers(2).keep_alive(None).spawn().unwrap();
+        let addr = server.local_addr();
+
+        assert!(request(addr, "GET /users/7 HTTP/1.1\r\nHost: x\r\n\r\n").ends_with("user 7"));
+        assert!(request(addr, "POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc").ends_with("\r\n\r\nabc"));
+        assert!(request(addr, "GET /echo HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        assert!(request(addr, "GET /nothing HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(request(addr, "garbage\r\n\r\n").starts_with("HTTP/1.1 400"));
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let mut router = Router::new();
+        router.get("/:n", |_, params| Response::text(params.get("n").unwrap().to_string()));
+        let server = Server::bind("127.0.0.1:0", router).unwrap().workers(1).max_requests(3).spawn().unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(b"GET /1 HTTP/1.1\r\n\r\nGET /2 HTTP/1.1\r\n\r\n").unwrap();
+        stream.write_all(b"GET /3 HTTP/1.1\r\n\r\nGET /4 HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        // The connection closes after `max_requests`, so /4 is never answered.
+        let bodies: Vec<&str> = response.split("HTTP/1.1 200 OK").skip(1).map(|r| r.rsplit("\r\n\r\n").next().unwrap()).collect();
+        assert_eq!(bodies, ["1", "2", "3"]);
+        assert_eq!(response.matches("Connection: keep-alive").count(), 2);
+        assert!(response.ends_with("Connection: close\r\nContent-Length: 1\r\n\r\n3"));
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn refuses_oversized_requests_early() {
+        let mut router = Router::new();
+        router.route(Method::POST, "/", |request, _| Response::text(request.body.len().to_string()));
+        let server = Server::bind("127.0.0.1:0", router).unwrap().max_head_size(256).max_body_size(1000).keep_alive(None).spawn().unwrap();
+        let addr = server.local_addr();
+
+        // Only the heads are sent, the answer must not wait for the bodies.
+        assert!(request(addr, "POST / HTTP/1.1\r\nContent-Length: 100000000000\r\n\r\n").starts_with("HTTP/1.1 413"));
+        assert!(request(addr, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n400\r\n").starts_with("HTTP/1.1 413"));
+        let chunks = "100\r\n".to_string() + &"x".repeat(256) + "\r\n";
+        let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}", chunks.repeat(4));
+        assert!(request(addr, &raw).starts_with("HTTP/1.1 413"));
+        let raw = format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(300));
+        assert!(request(addr, &raw).starts_with("HTTP/1.1 431"));
+        let raw = format!("POST / HTTP/1.1\r\nContent-Length: 1000\r\n\r\n{}", "x".repeat(1000));
+        assert!(request(addr, &raw).ends_with("\r\n\r\n1000"));
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn reads_large_chunked_bodies_in_small_pieces() {
+        let mut router = Router::new();
+        router.route(Method::POST, "/", |request, _| Response::text(request.body.len().to_string()));
+        let server = Server::bind("127.0.0.1:0", router).unwrap().max_requests(2).spawn().unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n").unwrap();
+        let piece = format!("200\r\n{}\r\n", "x".repeat(0x200));
+        for _ in 0..(4 << 20) / 0x200 {
+            stream.write_all(piece.as_bytes()).unwrap();
+        }
+        // The next request arrives in the same write as the end of the body.
+        stream.write_all(b"0\r\n\r\nPOST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.contains("\r\n\r\n4194304HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("\r\n\r\n2"));
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn refuses_upgrades_beyond_max_websockets() {
+        let (release, held) = mpsc::channel::<()>();
+        let held = Mutex::new(held);
+        let mut router = Router::new();
+        router.websocket("/hold", move |_, _, _| {
+            let _ = held.lock().unwrap().recv();
+        });
+        let server = Server::bind("127.0.0.1:0", router).unwrap().max_websockets(1).spawn().unwrap();
+        let url = format!("ws://{}/hold", server.local_addr());
+
+        let _first = WebSocket::connect(&url).unwrap();
+        let refused = WebSocket::connect(&url);
+        assert!(matches!(&refused, Err(crate::net::Error::Handshake(reason)) if reason.contains("503")), "{:?}", refused.err());
+
+        // The slot frees up once the handler returns.
+        release.send(()).unwrap();
+        let accepted = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            WebSocket::connect(&url).is_ok()
+        });
+        assert!(accepted);
+
+        drop(release);
+        server.shutdown();
+    }
+
+    #[test]
+    fn survives_panicking_handlers() {
+        let mut router = Router::new();
+        router.get("/panic", |_, _| panic!("handler bug"));
+        router.get("/ok", |_, _| Response::text("ok"));
+        let server = Server::bind("127.0.0.1:0", router).unwrap().workers(1).keep_alive(None).spawn().unwrap();
+        let addr = server.local_addr();
+
+        assert!(request(addr, "GET /panic HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 500"));
+        // The only worker is still alive.
+        assert!(request(addr, "GET /ok HTTP/1.1\r\n\r\n").ends_with("ok"));
+
+        server.shutdown();
+    }
+}
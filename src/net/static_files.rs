@@ -0,0 +1,250 @@
+use std::{
+    fs::{self, File},
+    io::{self, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    compression::encoded_etag,
+    date::{http_date, parse_http_date},
+    mime, Body, ContentEncoding, HTTPRequest, Method, Response,
+};
+
+/// Serves files below one root directory.
+///
+/// Request paths are resolved segment by segment and canonicalized, so neither
+/// `..` nor symlinks can reach files outside the root. Files are streamed from
+/// disk and support `Range` requests as well as `ETag`/`Last-Modified` based
+/// conditional requests.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Option<String>,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self { root: fs::canonicalize(root)?, index: Some("index.html".to_string()) })
+    }
+
+    /// File served for directory requests, `index.html` by default.
+    pub fn index_file(mut self, name: Option<&str>) -> Self {
+        self.index = name.map(str::to_string);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a decoded request path to a regular file inside the root.
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut full = self.root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => (),
+                ".." => return None,
+                s if s.contains(['\\', '\0', ':']) => return None,
+                s => full.push(s),
+            }
+        }
+
+        let mut full = self.contained(&full)?;
+        if full.is_dir() {
+            full = self.contained(&full.join(self.index.as_deref()?))?;
+        }
+        full.is_file().then_some(full)
+    }
+
+    fn contained(&self, path: &Path) -> Option<PathBuf> {
+        let path = fs::canonicalize(path).ok()?;
+        path.starts_with(&self.root).then_some(path)
+    }
+
+    pub fn serve(&self, request: &HTTPRequest) -> Response {
+        self.serve_path(request, &request.path)
+    }
+
+    /// Serves `path` relative to the root, e.g. the wildcard part of a route.
+    pub fn serve_path(&self, request: &HTTPRequest, path: &str) -> Response {
+        if request.method != Method::GET && request.method != Method::HEAD {
+            return Response::new(405).header("Allow", "GET, HEAD");
+        }
+        let Some(path) = self.resolve(path) else {
+            return Response::not_found();
+        };
+        match Self::file_response(request, &path) {
+            Ok(response) => response,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Response::not_found(),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Response::new(403),
+            Err(_) => Response::internal_error(),
+        }
+    }
+
+    fn file_response(request: &HTTPRequest, path: &Path) -> io::Result<Response> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let mtime = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let etag = format!("\"{:x}-{:x}{:08x}\"", len, mtime.as_secs(), mtime.subsec_nanos());
+        let last_modified = http_date(modified);
+
+        if let Some(etag) = fresh_etag(request, &etag, modified) {
+            return Ok(Response::not_modified().header("ETag", etag).header("Last-Modified", last_modified));
+        }
+
+        let content_type = mime::content_type_for_path(path);
+        let response = Response::ok()
+            .content_type(&content_type)
+            .header("ETag", etag.as_str())
+            .header("Last-Modified", last_modified.as_str())
+            .header("Accept-Ranges", "bytes");
+
+        let range = match request.headers.get("Range") {
+            Some(range) if if_range_matches(request, &etag, &last_modified) => parse_range(range, len),
+            _ => Ok(None),
+        };
+
+        match range {
+            Ok(Some((start, end))) => {
+                file.seek(SeekFrom::Start(start))?;
+                Ok(response
+                    .with_status(206)
+                    .header("Content-Range", format!("bytes {start}-{end}/{len}"))
+                    .body(Body::reader(file, Some(end - start + 1))))
+            }
+            Ok(None) => Ok(response.body(Body::reader(file, Some(len)))),
+            Err(()) => Ok(Response::new(416).header("Content-Range", format!("bytes */{len}"))),
+        }
+    }
+}
+
+/// Evaluates `If-None-Match` and, in its absence, `If-Modified-Since`, and
+/// returns the `ETag` for the 304 if the client's copy is fresh.
+///
+/// Tags of compressed representations match too, since
+/// [`Response::compress`] derives those from `etag`; the 304 then repeats
+/// the tag the client has.
+fn fresh_etag(request: &HTTPRequest, etag: &str, modified: SystemTime) -> Option<String> {
+    if let Some(tags) = request.headers.get("If-None-Match") {
+        let encoded = [ContentEncoding::Gzip, ContentEncoding::Deflate].map(|encoding| encoded_etag(etag, encoding));
+        return tags.split(',').map(|tag| tag.trim().trim_start_matches("W/")).find_map(|tag| match tag {
+            "*" => Some(etag.to_string()),
+            _ if tag == etag || encoded.iter().flatten().any(|encoded| encoded == tag) => Some(tag.to_string()),
+            _ => None,
+        });
+    }
+    let since = request.headers.get("If-Modified-Since").and_then(parse_http_date)?;
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let since = since.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    (modified <= since).then(|| etag.to_string())
+}
+
+/// A `Range` only applies if `If-Range` is absent or still matches the file.
+fn if_range_matches(request: &HTTPRequest, etag: &str, last_modified: &str) -> bool {
+    match request.headers.get("If-Range") {
+        Some(value) if value.starts_with('"') => value == etag,
+        Some(value) => value == last_modified,
+        None => true,
+    }
+}
+
+/// Parses a single byte range into inclusive bounds.
+///
+/// `Ok(None)` means the header is ignored and the whole file is sent, which
+/// also covers multi-range requests. `Err` means the range is unsatisfiable.
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+
+    if start.is_empty() {
+        let Ok(suffix) = end.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || len == 0 {
+            return Err(());
+        }
+        return Ok(Some((len.saturating_sub(suffix), len - 1)));
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return Ok(None);
+    };
+    let end = match end {
+        "" => len.saturating_sub(1),
+        end => match end.parse::<u64>() {
+            Ok(end) => end.min(len.saturating_sub(1)),
+            Err(_) => return Ok(None),
+        },
+    };
+    if start >= len || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::CompressionConfig;
+
+    fn get(path: &str, headers: &str) -> HTTPRequest {
+        HTTPRequest::parse(format!("GET {path} HTTP/1.1\r\n{headers}\r\n").as_bytes()).unwrap()
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        response.body.into_bytes().unwrap()
+    }
+
+    #[test]
+    fn serves_ranges_and_conditional_requests() {
+        let dir = std::env::temp_dir().join(format!("iron_oxide_static_{}", std::process::id()));
+        fs::create_dir_all(dir.join("public/sub")).unwrap();
+        fs::write(dir.join("public/sub/index.html"), "0123456789").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        let files = StaticFiles::new(dir.join("public")).unwrap();
+
+        assert_eq!(body(files.serve(&get("/sub/", ""))), b"0123456789");
+        assert_eq!(files.serve(&get("/../secret.txt", "")).status, 404);
+        assert_eq!(files.serve(&get("/sub/..%2F..%2Fsecret.txt", "")).status, 404);
+        #[cfg(unix)]
+        {
+            let _ = std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("public/link.txt"));
+            assert_eq!(files.serve(&get("/link.txt", "")).status, 404);
+        }
+
+        let response = files.serve(&get("/sub/index.html", "Range: bytes=2-4\r\n"));
+        assert_eq!(response.status, 206);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(body(response), b"234");
+        assert_eq!(body(files.serve(&get("/sub/index.html", "Range: bytes=-3\r\n"))), b"789");
+        assert_eq!(files.serve(&get("/sub/index.html", "Range: bytes=20-\r\n")).status, 416);
+
+        let etag = files.serve(&get("/sub/index.html", "")).headers.get("ETag").unwrap().to_string();
+        let cached = files.serve(&get("/sub/index.html", &format!("If-None-Match: {etag}\r\n")));
+        assert_eq!(cached.status, 304);
+
+        // Compression rewrites the tag; the client sends that one back.
+        fs::write(dir.join("public/big.txt"), "x".repeat(4096)).unwrap();
+        let gzip = "Accept-Encoding: gzip\r\n";
+        let request = get("/big.txt", gzip);
+        let response = files.serve(&request).compress(&request, &CompressionConfig::default());
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        let etag = response.headers.get("ETag").unwrap().to_string();
+        assert!(etag.ends_with("-gzip\""));
+        let request = get("/big.txt", &format!("{gzip}If-None-Match: {etag}\r\n"));
+        let cached = files.serve(&request).compress(&request, &CompressionConfig::default());
+        assert_eq!((cached.status, cached.headers.get("ETag")), (304, Some(etag.as_str())));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, Connection, ServerConfig, ServerConnection, StreamOwned};
+
+use super::Stream;
+
+struct Session {
+    conn: Connection,
+    sock: TcpStream,
+}
+
+/// A TLS connection over a [`TcpStream`]. The handshake runs on the first
+/// read or write.
+///
+/// Clones share one TLS session, so reading on one handle and writing on
+/// another works as it does for a plain socket.
+pub struct TlsStream {
+    session: Arc<Mutex<Session>>,
+    /// Held while reading from the socket and feeding the session, so the
+    /// records of concurrent reads can't get reordered. The session itself
+    /// stays unlocked during the blocking read.
+    reading: Arc<Mutex<()>>,
+    /// A second handle to the socket, for calls that don't touch the session.
+    tcp: TcpStream,
+}
+
+impl TlsStream {
+    /// Server side of a TLS connection on an accepted socket.
+    pub fn accept(sock: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let conn = ServerConnection::new(config).map_err(io::Error::other)?;
+        Self::new(conn.into(), sock)
+    }
+
+    /// Client side of a TLS connection to `server_name`, verified against `config`.
+    pub fn connect(sock: TcpStream, server_name: &str, config: Arc<ClientConfig>) -> io::Result<Self> {
+        let name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let conn = ClientConnection::new(config, name).map_err(io::Error::other)?;
+        Self::new(conn.into(), sock)
+    }
+
+    fn new(conn: Connection, sock: TcpStream) -> io::Result<Self> {
+        let tcp = sock.try_clone()?;
+        Ok(Self { session: Arc::new(Mutex::new(Session { conn, sock })), reading: Arc::default(), tcp })
+    }
+
+    pub(crate) fn tcp(&self) -> &TcpStream {
+        &self.tcp
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self { session: self.session.clone(), reading: self.reading.clone(), tcp: self.tcp.try_clone()? })
+    }
+
+    pub(crate) fn wants_write(&self) -> bool {
+        self.session().conn.wants_write()
+    }
+
+    /// Whether plaintext from records already taken off the socket is waiting.
+    pub(crate) fn has_buffered_data(&self) -> bool {
+        self.session().conn.process_new_packets().is_ok_and(|state| state.plaintext_bytes_to_read() > 0)
+    }
+
+    fn session(&self) -> MutexGuard<'_, Session> {
+        self.session.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn reading(&self) -> MutexGuard<'_, ()> {
+        self.reading.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs the handshake to its end before the first write. It reads from
+    /// the socket, so it takes the read lock first like [`Read`] does.
+    fn finish_handshake(&self) -> io::Result<()> {
+        if !self.session().conn.is_handshaking() {
+            return Ok(());
+        }
+        let _reading = self.reading();
+        let mut session = self.session();
+        let Session { conn, sock } = &mut *session;
+        if conn.is_handshaking() {
+            conn.complete_io(sock)?;
+        }
+        Ok(())
+    }
+}
+
+impl Session {
+    /// Feeds records read from the socket to the session, an empty `data`
+    /// meaning the peer closed it.
+    fn receive(&mut self, mut data: &[u8]) -> io::Result<()> {
+        loop {
+            self.conn.read_tls(&mut data)?;
+            if let Err(e) = self.conn.process_new_packets() {
+                // Tell the peer why, as rustls::Stream does.
+                let _ = self.send_buffered();
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            if data.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Writes buffered records until done or the socket would block.
+    fn send_buffered(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for &TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut incoming = [0; 16 * 1024];
+        loop {
+            let mut session = self.session();
+            // Handshake messages and alerts the last records called for.
+            session.send_buffered()?;
+            if !session.conn.is_handshaking() {
+                match session.conn.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    // The peer closed the socket without a close_notify.
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                    result => return result,
+                }
+            }
+            drop(session);
+
+            // Only the socket read blocks, and it does so without the session
+            // lock, so other clones can write meanwhile.
+            let _reading = self.reading();
+            let n = (&self.tcp).read(&mut incoming)?;
+            let mut session = self.session();
+            session.receive(&incoming[..n])?;
+            if n == 0 && session.conn.is_handshaking() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during the TLS handshake"));
+            }
+        }
+    }
+}
+
+impl Write for &TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.finish_handshake() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            result => result?,
+        }
+        let mut session = self.session();
+        match session.send_buffered() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            result => result?,
+        }
+        let n = session.conn.writer().write(buf)?;
+        if n == 0 && !buf.is_empty() {
+            // The send buffer is full until the socket takes some of it.
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        match session.send_buffered() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(n),
+            result => result.map(|_| n),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session();
+        session.conn.writer().flush()?;
+        session.send_buffered()?;
+        session.sock.flush()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl std::fmt::Debug for TlsStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsStream").field("tcp", &self.tcp).finish_non_exhaustive()
+    }
+}
+
+impl From<TlsStream> for Stream {
+    fn from(stream: TlsStream) -> Self {
+        Self::Tls(stream)
+    }
+}
+
+/// Takes over a connection set up with rustls directly.
+impl TryFrom<StreamOwned<ServerConnection, TcpStream>> for TlsStream {
+    type Error = io::Error;
+
+    fn try_from(stream: StreamOwned<ServerConnection, TcpStream>) -> io::Result<Self> {
+        Self::new(stream.conn.into(), stream.sock)
+    }
+}
+
+impl TryFrom<StreamOwned<ClientConnection, TcpStream>> for TlsStream {
+    type Error = io::Error;
+
+    fn try_from(stream: StreamOwned<ClientConnection, TcpStream>) -> io::Result<Self> {
+        Self::new(stream.conn.into(), stream.sock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{CloseCode, Message, Response, Router, Server, WebSocket, WebSocketInterface};
+    use rustls::{pki_types::{CertificateDer, PrivatePkcs8KeyDer}, RootCertStore};
+    use std::{net::SocketAddr, sync::{mpsc::{channel, Sender}, RwLock}};
+
+    fn configs() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert: CertificateDer<'static> = certified.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let server = ServerConfig::builder().with_no_client_auth().with_single_cert(vec![cert.clone()], key.into()).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        (Arc::new(server), Arc::new(client))
+    }
+
+    struct Echo {
+        ws: WebSocket,
+        received: Option<Sender<Message>>,
+    }
+
+    impl WebSocketInterface for Echo {
+        fn on_message(&mut self, message: Message) {
+            match &self.received {
+                Some(received) => {
+                    let _ = received.send(message);
+                    self.ws.close().unwrap();
+                }
+                None => self.ws.send_message(message).unwrap(),
+            }
+        }
+        fn on_closed(&self, _ip: SocketAddr, _code: CloseCode, _reason: &str) {}
+        fn websocket(&self) -> &WebSocket {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket {
+            &mut self.ws
+        }
+    }
+
+    #[test]
+    fn serves_https_and_wss_with_a_self_signed_certificate() {
+        let (server_config, client_config) = configs();
+        let mut router = Router::new();
+        router.get("/hello", |_, _| Response::text("hello over tls"));
+        router.websocket("/echo", |ws, _, _| {
+            let _ = WebSocket::run(Arc::new(RwLock::new(Echo { ws, received: None })));
+        });
+        #[cfg(unix)]
+        let reactor = Arc::new(crate::net::Reactor::new(1).unwrap());
+        #[cfg(unix)]
+        {
+            let reactor = reactor.clone();
+            router.websocket("/reactor", move |ws, _, _| {
+                let _ = reactor.add(Echo { ws, received: None });
+            });
+        }
+        let server = Server::bind("127.0.0.1:0", router).unwrap().keep_alive(None).tls(server_config).spawn().unwrap();
+        let port = server.local_addr().port();
+
+        let tcp = TcpStream::connect(server.local_addr()).unwrap();
+        let conn = ClientConnection::new(client_config.clone(), ServerName::try_from("localhost").unwrap()).unwrap();
+        let mut https = StreamOwned::new(conn, tcp);
+        https.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = https.read_to_end(&mut response);
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK") && response.ends_with("hello over tls"), "{response}");
+
+        // Plain HTTP gets nowhere on a TLS port.
+        let mut plain = TcpStream::connect(server.local_addr()).unwrap();
+        plain.write_all(b"GET /hello HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response);
+        assert!(!response.starts_with(b"HTTP/1.1 200"));
+
+        let mut ws = WebSocket::connect_tls(&format!("wss://localhost:{port}/echo"), None, client_config.clone()).unwrap();
+        assert!(ws.stream().is_tls());
+        ws.send_message(Message::Text("secret".into())).unwrap();
+        let (sender, received) = channel();
+        WebSocket::run(Arc::new(RwLock::new(Echo { ws, received: Some(sender) }))).unwrap();
+        assert_eq!(received.recv().unwrap(), Message::Text("secret".into()));
+
+        // Non-blocking TLS sockets work on the reactor as well.
+        #[cfg(unix)]
+        {
+            let mut ws = WebSocket::connect_tls(&format!("wss://localhost:{port}/reactor"), None, client_config).unwrap();
+            let large = "x".repeat(1 << 20);
+            ws.send_message(Message::Text(large.clone())).unwrap();
+            let (sender, received) = channel();
+            WebSocket::run(Arc::new(RwLock::new(Echo { ws, received: Some(sender) }))).unwrap();
+            assert_eq!(received.recv().unwrap(), Message::Text(large));
+        }
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn writes_while_a_clone_is_blocked_reading() {
+        let (server_config, client_config) = configs();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut tls = TlsStream::accept(listener.accept().unwrap().0, server_config).unwrap();
+            let mut request = [0; 5];
+            tls.read_exact(&mut request).unwrap();
+            tls.write_all(b"pong!").unwrap();
+            tls.flush().unwrap();
+            request
+        });
+
+        let client = TlsStream::connect(TcpStream::connect(addr).unwrap(), "localhost", client_config).unwrap();
+        let mut reader = client.try_clone().unwrap();
+        let reply = std::thread::spawn(move || {
+            let mut reply = [0; 5];
+            reader.read_exact(&mut reply).unwrap();
+            reply
+        });
+        // Let the reader block first; the server only answers after the write.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let (written, done) = channel();
+        std::thread::spawn(move || {
+            let mut client = client;
+            client.write_all(b"ping!").unwrap();
+            client.flush().unwrap();
+            written.send(client).unwrap();
+        });
+        let _client = done.recv_timeout(std::time::Duration::from_secs(5)).expect("write blocked by the reader");
+
+        assert_eq!(&server.join().unwrap(), b"ping!");
+        assert_eq!(&reply.join().unwrap(), b"pong!");
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use super::Stream;
+#[cfg(feature = "tls")]
+use super::TlsStream;
+
+/// A byte stream a [`WebSocket`](super::WebSocket) can run over.
+///
+/// Like a socket, clones from [`Transport::try_clone`] must refer to the same
+/// connection, so one handle can read while another one writes.
+pub trait Transport: Read + Write + Sized {
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// Closes both directions; pending and later reads on any clone return 0.
+    fn shutdown(&self) -> io::Result<()>;
+
+    /// Reported to [`WebSocketInterface::on_closed`](super::WebSocketInterface::on_closed).
+    /// Transports without one return [`unspecified_addr`].
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Reads fail with `WouldBlock` or `TimedOut` after `timeout`, which lets
+    /// `WebSocket::run` send heartbeats while the peer is quiet.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// A pending error on the connection, like `SO_ERROR` on sockets.
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        Ok(None)
+    }
+}
+
+/// `0.0.0.0:0`, the peer address of transports that have none.
+pub fn unspecified_addr() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        TcpStream::take_error(self)
+    }
+}
+
+impl Transport for Stream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Stream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        Stream::shutdown(self, Shutdown::Both)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Stream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        Stream::set_read_timeout(self, timeout)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        Stream::take_error(self)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Transport for TlsStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TlsStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.tcp().shutdown(Shutdown::Both)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(timeout)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.tcp().take_error()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        std::os::unix::net::UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(unspecified_addr())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        std::os::unix::net::UnixStream::take_error(self)
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+use super::ParseError;
+
+/// The parts of an absolute `http`, `https`, `ws` or `wss` URL that a client
+/// needs to open a connection and send a request line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16,
+    /// Path plus query string, always starting with `/`.
+    pub target: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, ParseError> {
+        let (scheme, rest) = url.split_once("://").ok_or(ParseError::InvalidUrl)?;
+        let scheme = scheme.to_ascii_lowercase();
+        let default_port = match scheme.as_str() {
+            "http" | "ws" => 80,
+            "https" | "wss" => 443,
+            _ => return Err(ParseError::InvalidUrl),
+        };
+
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, target) = rest.split_at(end);
+        let target = target.split('#').next().unwrap_or("");
+        let target = match target {
+            "" => "/".to_string(),
+            t if t.starts_with('?') => format!("/{t}"),
+            t => t.to_string(),
+        };
+
+        // Credentials are not supported, but must not end up in the host.
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => {
+                let (host, rest) = v6.split_once(']').ok_or(ParseError::InvalidUrl)?;
+                (host, rest.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(ParseError::InvalidUrl);
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| ParseError::InvalidUrl)?,
+            None => default_port,
+        };
+
+        Ok(Self { scheme, host: host.to_string(), port, target })
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.scheme == "https" || self.scheme == "wss"
+    }
+
+    fn default_port(&self) -> u16 {
+        if self.is_secure() { 443 } else { 80 }
+    }
+
+    /// Value for the `Host` header, which omits the default port.
+    pub fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == self.default_port() {
+            host
+        } else {
+            format!("{host}:{}", self.port)
+        }
+    }
+
+    /// Resolves a `Location` header value against this URL.
+    pub fn join(&self, location: &str) -> Result<Self, ParseError> {
+        if location.contains("://") {
+            return Self::parse(location);
+        }
+        let target = if let Some(authority_relative) = location.strip_prefix("//") {
+            return Self::parse(&format!("{}://{authority_relative}", self.scheme));
+        } else if location.starts_with('/') {
+            location.to_string()
+        } else {
+            let path = self.target.split('?').next().unwrap_or("/");
+            let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            format!("{dir}{location}")
+        };
+        Ok(Self { target, ..self.clone() })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}{}", self.scheme, self.host_header(), self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_joins() {
+        let url = Url::parse("http://localhost:8080/a/b?x=1#frag").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.target.as_str()), ("localhost", 8080, "/a/b?x=1"));
+        assert_eq!(url.join("c").unwrap().to_string(), "http://localhost:8080/a/c");
+        assert_eq!(url.join("/d").unwrap().to_string(), "http://localhost:8080/d");
+        assert_eq!(Url::parse("wss://[::1]/chat").unwrap().host_header(), "[::1]");
+        assert_eq!(Url::parse("ftp://x"), Err(ParseError::InvalidUrl));
+    }
+}
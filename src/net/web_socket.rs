@@ -1,417 +1,961 @@
-use std::{collections::VecDeque, io::{Read, Write}, net::{SocketAddr, TcpStream}, sync::{Arc, RwLock}, time::Duration};
+use std::{collections::VecDeque, io::{self, Read, Write}, net::{SocketAddr, TcpStream, ToSocketAddrs}, sync::{Arc, PoisonError, RwLock, RwLockWriteGuard}, time::{Duration, Instant}};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use sha1_smol::Sha1;
 
+use crate::io::{Decode, Encode, ReadError};
+use super::{log::net_log, permessage_deflate::{self, Deflater, InflateError, Inflater}, CloseCode, DeflateConfig, Error, Frame, HTTPRequest, Method, OpCode, Response, Result, Stream, Transport, Url};
+
+/// Which end of the connection this socket is. Clients mask every frame they
+/// send, servers never do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/// A WebSocket connection over any [`Transport`]. The server and
+/// [`WebSocket::connect`] produce one over a [`Stream`].
 #[derive(Debug)]
-pub struct WebSocket {
-    stream: TcpStream,
+pub struct WebSocket<T: Transport = Stream> {
+    stream: T,
     send_queue: VecDeque<Vec<u8>>,
-    close: bool,
+    /// When our close frame was queued; the connection ends once the peer
+    /// answers or `CLOSE_TIMEOUT` passes.
+    closing: Option<Instant>,
+    role: Role,
+    /// Bytes that arrived together with the handshake response.
+    pending: Vec<u8>,
+    /// How much of the front of `send_queue` is already written.
+    written: usize,
+    /// Set when `permessage-deflate` was negotiated.
+    deflater: Option<Deflater>,
+    inflater: Option<Inflater>,
+    heartbeat: Option<Heartbeat>,
+}
+
+/// Periodic pings that detect dead peers and measure round-trip time.
+#[derive(Debug)]
+struct Heartbeat {
+    interval: Duration,
+    timeout: Duration,
+    next_ping: Instant,
+    /// Sequence number and send time of the ping awaiting its pong.
+    outstanding: Option<(u64, Instant)>,
+    sequence: u64,
+    latency: Option<Duration>,
 }
 
 #[allow(dead_code)]
 impl WebSocket {
 
-    pub fn try_connect(mut stream: TcpStream, handshake_key: &str) -> Option<Self> {
-
-        let mut sha = Sha1::new();
-        sha.update(handshake_key.as_bytes());
-        sha.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
-        let umbrella = sha.digest().bytes();
-        let mut encoded_key = [0; 28];
-        STANDARD.encode_slice(umbrella, &mut encoded_key).unwrap();
+    pub fn try_connect(stream: impl Into<Stream>, handshake_key: &str) -> Result<Self> {
+        Self::try_connect_with(stream, handshake_key, None, None)
+    }
 
-        let response = format!("HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Accept: {}\r\n\r\n", unsafe {String::from_utf8_unchecked(encoded_key.to_vec())});
-        stream.write(response.as_bytes()).unwrap();
-        stream.flush().unwrap();
-        stream.set_read_timeout(Some(Duration::from_millis(1000))).unwrap();
+    /// Like [`WebSocket::try_connect`], but also answers the client's
+    /// `Sec-WebSocket-Extensions` offer if `deflate` is set.
+    pub fn try_connect_with(stream: impl Into<Stream>, handshake_key: &str, extensions: Option<&str>, deflate: Option<&DeflateConfig>) -> Result<Self> {
+        let mut stream = stream.into();
+        let negotiated = extensions
+            .zip(deflate)
+            .and_then(|(offer, config)| Some((permessage_deflate::accept_offer(offer, config)?, config.level)));
 
-        let ws = Self { stream, send_queue: VecDeque::with_capacity(10), close: false };
-        Some(ws)
-    }
+        let mut response = format!("HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Accept: {}\r\n", accept_key(handshake_key));
+        if let Some(((extension, _), _)) = &negotiated {
+            response.push_str(&format!("Sec-WebSocket-Extensions: {extension}\r\n"));
+        }
+        response.push_str("\r\n");
+        stream.write_all(response.as_bytes())?;
+        stream.flush()?;
+        stream.set_read_timeout(Some(Duration::from_millis(1000)))?;
 
-    pub fn new(stream: TcpStream) -> Self {
-        let ws = WebSocket { stream, send_queue: VecDeque::with_capacity(10), close: false };
-        ws
+        let mut ws = Self::from_stream(stream, Role::Server, Vec::new());
+        if let Some(((_, negotiated), level)) = negotiated {
+            let (deflater, inflater) = negotiated.into_parts(level);
+            ws.deflater = Some(deflater);
+            ws.inflater = Some(inflater);
+        }
+        Ok(ws)
     }
 
-    pub fn close(&mut self) {
-        self.close = true;
+    /// Opens a client connection to a `ws://` URL and performs the opening
+    /// handshake, including the `Sec-WebSocket-Accept` check.
+    pub fn connect(url: &str) -> Result<Self> {
+        Self::connect_with(url, None)
     }
 
-    pub fn send_ping(&mut self) {
-        let mut ping = Vec::with_capacity(2);
-        ping.push(0b10001001); // FIN und Opcode für Ping
-        ping.push(1);
-        ping.push(95);        // Länge 0 für Ping
-        self.send_queue.push_back(ping);
+    /// Like [`WebSocket::connect`], offering `permessage-deflate` if `deflate` is set.
+    pub fn connect_with(url: &str, deflate: Option<DeflateConfig>) -> Result<Self> {
+        let url = Url::parse(url)?;
+        if url.scheme != "ws" {
+            return Err(Error::UnsupportedScheme(url.scheme));
+        }
+        let stream = connect_tcp(&url)?;
+        Self::handshake(stream.into(), &url, deflate, CONNECT_TIMEOUT)
     }
 
-    pub fn send_pong(&mut self, data: Vec<u8>) {
-        let mut pong = Vec::with_capacity(data.len() + 2);
-        pong.push(0b10001000); // FIN und Opcode für Pong
-        pong.push(data.len() as u8);  // Länge des Pongs
-        pong.extend_from_slice(&data); // Daten des Pongs
-        self.send_queue.push_back(pong);
+    /// Opens a client connection to a `wss://` URL, verifying the server
+    /// with `config`.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(url: &str, deflate: Option<DeflateConfig>, config: Arc<rustls::ClientConfig>) -> Result<Self> {
+        let url = Url::parse(url)?;
+        if url.scheme != "wss" {
+            return Err(Error::UnsupportedScheme(url.scheme));
+        }
+        let tcp = connect_tcp(&url)?;
+        let stream = super::TlsStream::connect(tcp, &url.host, config)?;
+        Self::handshake(stream.into(), &url, deflate, CONNECT_TIMEOUT)
     }
 
-    pub fn send(&mut self, message: &[u8], msg_type: MessageDataType) {
-        let mut m: Vec<u8> = Vec::with_capacity(message.len() + 14);
-        let len = message.len();
-        //let mask: [u8; 4] = [milis ^ 196, milis ^ 27, milis ^ 157, milis ^ 186];
+    /// Client side of the opening handshake, which fails if the server takes
+    /// longer than `timeout` to answer.
+    fn handshake(mut stream: Stream, url: &Url, deflate: Option<DeflateConfig>, timeout: Duration) -> Result<Self> {
+        stream.set_read_timeout(Some(timeout))?;
+        let key = STANDARD.encode(random_bytes::<16>()?);
+        let mut request = HTTPRequest::new(Method::GET, &url.target);
+        request.host = Some(url.host_header());
+        request.headers.set("Upgrade", "websocket");
+        request.headers.set("Connection", "Upgrade");
+        request.headers.set("Sec-WebSocket-Key", key.as_str());
+        request.headers.set("Sec-WebSocket-Version", "13");
+        if let Some(config) = &deflate {
+            request.headers.set("Sec-WebSocket-Extensions", permessage_deflate::client_offer(config));
+        }
+        request.write_to(&mut stream)?;
+        stream.flush()?;
 
-        match len {
-            0..=125 => {
-                m.push(0b10000000 | msg_type as u8);
-                m.push(len as u8);
-                m.extend_from_slice(message);
+        let mut buffer = Vec::with_capacity(1024);
+        let mut chunk = [0; 1024];
+        let (response, body_start) = loop {
+            if let Some(head) = Response::parse_head(&buffer)? {
+                break head;
             }
-            0..=65535 => {
-                m.push(0b10000000 | msg_type as u8);
-                m.push(126);
-                m.extend_from_slice(&(len as u16).to_be_bytes());
-                m.extend_from_slice(message);
-            },
-            _ => {
-                m.push(0b10000000 | msg_type as u8);
-                m.push(127);
-                m.extend_from_slice(&(len as u64).to_be_bytes());
-                m.extend_from_slice(message);
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(Error::Handshake("connection closed during handshake".to_string())),
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Err(Error::Handshake("no handshake response in time".to_string()));
+                }
+                Err(e) => return Err(e.into()),
             }
-        }
-        self.send_queue.push_back(m);
-    }
+        };
 
-    fn get_payloadlen(data: &[u8]) -> MessageLen {
-        if data.len() < 3 {
-            return MessageLen::ToShort;
+        let accepted = response.status == 101
+            && response.headers.has_token("Upgrade", "websocket")
+            && response.headers.has_token("Connection", "upgrade")
+            && response.headers.get("Sec-WebSocket-Accept") == Some(accept_key(&key).as_str());
+        if !accepted {
+            return Err(Error::Handshake(format!("rejected with status {}", response.status)));
         }
-        let len  = data[1] & 0b01111111;
 
-        let actual_len: u64 = match len {
-            126 => {
-                if data.len() < 4 {
-                    return MessageLen::MissingBytes(4 - data.len());
-                }
-                let len = ((data[2] as u64) << 8) | (data[3] as u64);
-                len + 8
+        let extensions = response.headers.get("Sec-WebSocket-Extensions");
+        let negotiated = match &deflate {
+            Some(config) => permessage_deflate::accept_response(extensions, config)
+                .map_err(Error::Handshake)?
+                .map(|negotiated| negotiated.into_parts(config.level)),
+            None if extensions.is_some() => {
+                return Err(Error::Handshake("server accepted an extension that was not offered".to_string()));
             }
-            127 => {
-                if data.len() < 10 {
-                    return MessageLen::MissingBytes(10 - data.len());
+            None => None,
+        };
+
+        stream.set_read_timeout(Some(Duration::from_millis(1000)))?;
+        buffer.drain(..body_start);
+        let mut ws = Self::from_stream(stream, Role::Client, buffer);
+        if let Some((deflater, inflater)) = negotiated {
+            ws.deflater = Some(deflater);
+            ws.inflater = Some(inflater);
+        }
+        Ok(ws)
+    }
+
+    pub fn new(stream: impl Into<Stream>) -> Self {
+        Self::from_stream(stream.into(), Role::Server, Vec::new())
+    }
+
+    /// Writes as much of the send queue as a non-blocking socket accepts.
+    /// Returns whether the queue is now empty.
+    pub(crate) fn write_pending(&mut self) -> io::Result<bool> {
+        while let Some(message) = self.send_queue.front() {
+            match self.stream.write(&message[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.written += n;
+                    if self.written == message.len() {
+                        self.send_queue.pop_front();
+                        self.written = 0;
+                    }
                 }
-                let len = ((data[2] as u64) << 56) | ((data[3] as u64) << 48) |
-                ((data[4] as u64) << 40) | ((data[5] as u64) << 32) |
-                ((data[6] as u64) << 24) | ((data[7] as u64) << 16) |
-                ((data[8] as u64) << 8) | (data[9] as u64);
-                len + 14
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
             }
-            _ => len as u64 + 6
+        }
+        // TLS may still hold records the socket did not take.
+        match self.stream.flush() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            result => result.map(|_| true),
+        }
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.send_queue.is_empty() || self.stream.wants_write()
+    }
+}
+
+#[allow(dead_code)]
+impl<T: Transport> WebSocket<T> {
+    /// Wraps a transport on which the opening handshake already happened.
+    pub fn from_transport(stream: T, role: Role) -> Self {
+        Self::from_stream(stream, role, Vec::new())
+    }
+
+    fn from_stream(stream: T, role: Role, pending: Vec<u8>) -> Self {
+        Self { stream, send_queue: VecDeque::with_capacity(10), closing: None, role, pending, written: 0, deflater: None, inflater: None, heartbeat: None }
+    }
+
+    /// Whether `permessage-deflate` is in use on this connection.
+    pub fn is_compressed(&self) -> bool {
+        self.deflater.is_some()
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Number of frames waiting to be written.
+    pub fn queued(&self) -> usize {
+        self.send_queue.len()
+    }
+
+    /// Drops the connection at once, without a close handshake.
+    pub fn disconnect(&mut self) {
+        self.send_queue.clear();
+        self.written = 0;
+        let _ = self.stream.shutdown();
+    }
+
+    /// Queues a frame, masking it with a fresh key when acting as client.
+    /// Nothing is sent after the close frame. Fails only if no masking key
+    /// could be generated.
+    pub fn send_frame(&mut self, mut frame: Frame) -> io::Result<()> {
+        if self.closing.is_some() {
+            return Ok(());
+        }
+        let closes = frame.opcode == OpCode::Close;
+        // Only whole single-frame messages are compressed.
+        if let Some(deflater) = &mut self.deflater
+            && frame.fin
+            && !frame.rsv1
+            && matches!(frame.opcode, OpCode::Text | OpCode::Binary)
+            && let Ok(Some(compressed)) = deflater.compress(&frame.payload)
+        {
+            frame.payload = compressed;
+            frame.rsv1 = true;
+        }
+        self.send_queue.push_back(outgoing(frame, self.role)?.encode());
+        if closes {
+            self.closing = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> io::Result<()> {
+        self.close_with(CloseCode::NORMAL, "")
+    }
+
+    /// Starts the close handshake. `run` keeps reading until the peer
+    /// answers with its own close frame.
+    pub fn close_with(&mut self, code: CloseCode, reason: &str) -> io::Result<()> {
+        self.send_frame(Frame::close(Some(code), reason))
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.is_some()
+    }
+
+    pub fn send_ping(&mut self) -> io::Result<()> {
+        self.send_frame(Frame::ping(Vec::new()))
+    }
+
+    pub fn send_pong(&mut self, data: Vec<u8>) -> io::Result<()> {
+        self.send_frame(Frame::pong(data))
+    }
+
+    pub fn send(&mut self, message: &[u8], msg_type: MessageDataType) -> io::Result<()> {
+        let opcode = match msg_type {
+            MessageDataType::Text => OpCode::Text,
+            MessageDataType::Binary | MessageDataType::Continue => OpCode::Binary,
         };
+        self.send_frame(Frame::new(opcode, message))
+    }
 
-        MessageLen::Len(actual_len as usize)
+    pub fn send_message(&mut self, message: Message) -> io::Result<()> {
+        self.send_frame(match message {
+            Message::Text(text) => Frame::text(text),
+            Message::Binary(data) => Frame::binary(data),
+        })
+    }
+
+    /// Sends `value` as a binary message in its [`Encode`] form.
+    pub fn send_encoded(&mut self, value: &impl Encode) -> io::Result<()> {
+        self.send_frame(Frame::binary(value.to_bytes()))
     }
 
     /// **Verarbeitet ausgehende Nachrichten**
-    fn flush(&mut self) -> Option<()> {
+    fn flush(&mut self) -> io::Result<()> {
         while let Some(message) = self.send_queue.pop_front() {
-            if let Err(_) = self.stream.write_all(&message) {
-                return None;
-            }
+            self.stream.write_all(&message[self.written..])?;
+            self.written = 0;
         }
-        self.stream.flush().unwrap();
-        Some(())
+        self.stream.flush()
+    }
+
+    pub(crate) fn stream(&self) -> &T {
+        &self.stream
+    }
+
+    /// Pings the peer every `interval` and gives up on it if a pong takes
+    /// longer than `timeout`. `run` and the `Reactor` drive it through [`WebSocket::tick`].
+    pub fn set_heartbeat(&mut self, interval: Duration, timeout: Duration) {
+        // `run` only ticks between reads.
+        let _ = self.stream.set_read_timeout(Some(interval.min(timeout).min(Duration::from_secs(1))));
+        self.heartbeat = Some(Heartbeat {
+            interval,
+            timeout,
+            next_ping: Instant::now() + interval,
+            outstanding: None,
+            sequence: 0,
+            latency: None,
+        });
     }
 
-    pub fn run(ws_interface: Arc<RwLock<impl WebSocketInterface>>) {
+    pub fn disable_heartbeat(&mut self) {
+        self.heartbeat = None;
+    }
+
+    /// Round-trip time of the last answered heartbeat ping.
+    pub fn latency(&self) -> Option<Duration> {
+        self.heartbeat.as_ref()?.latency
+    }
+
+    /// Sends a heartbeat ping when one is due. Returns `false` if the peer
+    /// failed to answer the previous one in time or the ping could not be queued.
+    pub fn tick(&mut self) -> bool {
+        let Some(heartbeat) = &mut self.heartbeat else {
+            return true;
+        };
+        let now = Instant::now();
+        if let Some((_, sent)) = heartbeat.outstanding {
+            return now.duration_since(sent) <= heartbeat.timeout;
+        }
+        if now < heartbeat.next_ping || self.closing.is_some() {
+            return true;
+        }
+        heartbeat.sequence += 1;
+        heartbeat.outstanding = Some((heartbeat.sequence, now));
+        let payload = heartbeat.sequence.to_be_bytes();
+        self.send_frame(Frame::ping(payload)).is_ok()
+    }
+
+    /// When `tick` next has work to do.
+    pub(crate) fn next_tick(&self) -> Option<Instant> {
+        let heartbeat = self.heartbeat.as_ref()?;
+        Some(match heartbeat.outstanding {
+            Some((_, sent)) => sent + heartbeat.timeout,
+            None => heartbeat.next_ping,
+        })
+    }
+
+    /// Matches a pong against the outstanding heartbeat ping.
+    fn pong_received(&mut self, data: &[u8]) {
+        if let Some(heartbeat) = &mut self.heartbeat
+            && let Some((sequence, sent)) = heartbeat.outstanding
+            && data == sequence.to_be_bytes()
+        {
+            let now = Instant::now();
+            heartbeat.latency = Some(now.duration_since(sent));
+            heartbeat.outstanding = None;
+            heartbeat.next_ping = sent + heartbeat.interval;
+        }
+    }
+
+    /// Whether we sent a close frame and the peer failed to answer in time.
+    pub(crate) fn close_timed_out(&self) -> bool {
+        self.closing.is_some_and(|since| since.elapsed() > CLOSE_TIMEOUT)
+    }
+
+    /// Serves the connection on the current thread until it closes. Only
+    /// fails if the connection cannot be set up; everything after that is
+    /// reported through [`WebSocketInterface::on_closed`].
+    pub fn run(ws_interface: Arc<RwLock<impl WebSocketInterface<T>>>) -> Result<()> {
         let mut stream;
+        let mut incoming;
         {
-            let interface = ws_interface.read().unwrap();
-            stream = interface.websocket().stream.try_clone().unwrap();
+            let mut interface = lock(&ws_interface);
+            let ws = interface.websocket_mut();
+            stream = ws.stream.try_clone()?;
+            incoming = Incoming::new(ws);
         }
-    
-        let ip = stream.peer_addr().unwrap();
+
+        let ip = stream.peer_addr()?;
         let mut buffer = [0; 8192];
-        
-        let mut message_buffer: Vec<u8> = Vec::new();
-        let mut incomplete_message: Vec<u8> = Vec::new();
-        let mut required_length: Option<usize> = None;
-        let mut is_fragmented = false;
-        let mut bytes_processed;
-        let mut expected_fragment_type = None;
-    
+
         loop {
-            stream.take_error().expect("No error was expected...");
+            if let Ok(Some(e)) | Err(e) = stream.take_error() {
+                net_log!(Debug, "WebSocket {ip}: socket error: {e}");
+                lock(&ws_interface).on_closed(ip, CloseCode::ABNORMAL, "");
+                return Ok(());
+            }
 
-            //if  
-    
-            match stream.read(&mut buffer) {
-                Ok(0) => {
-                    println!("Connection closed");
-                    let client = ws_interface.write().unwrap();
-                    client.on_closed(ip);
-                    return;
-                },
-                Ok(bytes_read) => {
-                    bytes_processed = 0;
-    
-                    while bytes_processed < bytes_read {
-                        if !incomplete_message.is_empty() {
-                            let remaining_bytes = match required_length {
-                                Some(len) => len.saturating_sub(incomplete_message.len()),
-                                None => {
-                                    // Wenn wir noch keine Länge haben, versuchen wir sie zu bestimmen
-                                    match Self::get_payloadlen(&incomplete_message) {
-                                        MessageLen::Len(total_len) => {
-                                            // Sobald wir die Länge kennen, reservieren wir den Speicher
-                                            incomplete_message.reserve(total_len);
-                                            required_length = Some(total_len);
-                                            total_len.saturating_sub(incomplete_message.len())
-                                        },
-                                        MessageLen::MissingBytes(missing) => missing,
-                                        MessageLen::ToShort => {
-                                            let to_add = std::cmp::min(
-                                                bytes_read - bytes_processed,
-                                                3 - incomplete_message.len()
-                                            );
-                                            incomplete_message.extend_from_slice(
-                                                &buffer[bytes_processed..bytes_processed + to_add]
-                                            );
-                                            bytes_processed += to_add;
-                                            continue;
-                                        }
-                                    }
-                                }
-                            };
-    
-                            let bytes_to_add = std::cmp::min(bytes_read - bytes_processed, remaining_bytes);
-                            incomplete_message.extend_from_slice(
-                                &buffer[bytes_processed..bytes_processed + bytes_to_add]
-                            );
-                            bytes_processed += bytes_to_add;
-    
-                            if let Some(len) = required_length {
-                                if incomplete_message.len() >= len {
-                                    Self::process_message(
-                                        &incomplete_message,
-                                        &mut is_fragmented,
-                                        &mut message_buffer,
-                                        &mut expected_fragment_type,
-                                        ws_interface.clone(),
-                                        &mut stream,
-                                        ip
-                                    );
-                                    incomplete_message.clear();
-                                    required_length = None;
-                                }
-                            }
-                            continue;
-                        }
-    
-                        match Self::get_payloadlen(&buffer[bytes_processed..bytes_read]) {
-                            MessageLen::Len(len) => {
-                                if bytes_processed + len <= bytes_read {
-                                    Self::process_message(
-                                        &buffer[bytes_processed..bytes_processed + len],
-                                        &mut is_fragmented,
-                                        &mut message_buffer,
-                                        &mut expected_fragment_type,
-                                        ws_interface.clone(),
-                                        &mut stream,
-                                        ip
-                                    );
-                                    bytes_processed += len;
-                                } else {
-                                    // Reserviere die Kapazität für die komplette Nachricht
-                                    incomplete_message = Vec::with_capacity(len);
-                                    incomplete_message.extend_from_slice(
-                                        &buffer[bytes_processed..bytes_read]
-                                    );
-                                    required_length = Some(len);
-                                    bytes_processed = bytes_read;
-                                }
-                            },
-                            MessageLen::MissingBytes(missing) => {
-                                // Reserviere initial nur für den Header
-                                incomplete_message = Vec::with_capacity(missing + 10);
-                                incomplete_message.extend_from_slice(
-                                    &buffer[bytes_processed..bytes_read]
-                                );
-                                required_length = Some(missing);
-                                bytes_processed = bytes_read;
-                            },
-                            MessageLen::ToShort => {
-                                // Reserviere initial für einen kleinen Header
-                                incomplete_message = Vec::with_capacity(14);
-                                incomplete_message.extend_from_slice(
-                                    &buffer[bytes_processed..bytes_read]
-                                );
-                                bytes_processed = bytes_read;
-                            }
+            loop {
+                match incoming.next_event() {
+                    Ok(Some(event)) => {
+                        let mut client = lock(&ws_interface);
+                        if !deliver(&mut *client, event, ip) {
+                            let _ = client.websocket_mut().flush();
+                            return Ok(());
                         }
                     }
+                    Ok(None) => break,
+                    Err(code) => {
+                        net_log!(Info, "WebSocket {ip}: closing connection: {code}");
+                        let mut client = lock(&ws_interface);
+                        fail(&mut *client, code, ip);
+                        let _ = client.websocket_mut().flush();
+                        return Ok(());
+                    }
+                }
+            }
+
+            {
+                let mut client = lock(&ws_interface);
+                let ws = client.websocket_mut();
+                if !ws.tick() {
+                    ws.disconnect();
+                    client.on_closed(ip, CloseCode::ABNORMAL, "heartbeat timed out");
+                    return Ok(());
+                } else if let Err(e) = ws.flush() {
+                    net_log!(Debug, "WebSocket {ip}: write failed: {e}");
+                    client.on_closed(ip, CloseCode::ABNORMAL, "");
+                    return Ok(());
+                } else if ws.close_timed_out() {
+                    client.on_closed(ip, CloseCode::ABNORMAL, "close handshake timed out");
+                    return Ok(());
+                }
+            }
+
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    net_log!(Debug, "WebSocket {ip}: connection closed");
+                    lock(&ws_interface).on_closed(ip, CloseCode::ABNORMAL, "");
+                    return Ok(());
                 },
-                Err(e) if e.kind() == std::io::ErrorKind::TimedOut || e.kind() == std::io::ErrorKind::WouldBlock => (),
+                Ok(bytes_read) => incoming.extend(&buffer[..bytes_read]),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
                 Err(e) => {
-                    println!("Error occurred: {e}");
-                    let client = ws_interface.write().unwrap();
-                    client.on_closed(ip);
-                    return;
+                    net_log!(Debug, "WebSocket {ip}: read failed: {e}");
+                    lock(&ws_interface).on_closed(ip, CloseCode::ABNORMAL, "");
+                    return Ok(());
                 }
             }
-    
-            let mut client = ws_interface.write().unwrap();
-            let ws = client.websocket_mut();
-            if ws.flush().is_none() {
-                client.on_closed(ip);
-                return;
-            } else if ws.close {
-                return;
-            }
         }
     }
 
-    fn process_message(
-        message: &[u8],
-        is_frag: &mut bool,
-        message_buffer: &mut Vec<u8>,
-        expected_type: &mut Option<u8>,
-        ws_interface: Arc<RwLock<impl WebSocketInterface>>,
-        stream: &mut TcpStream,
-        ip: SocketAddr
-    ) {
-        let fin = (message[0] & 0b10000000) != 0;
-        let opt_code = message[0] & 0b00001111;
-        let len = message[1] & 0b01111111;
-        let mask_bit = (message[1] & 0b10000000) != 0;
-    
-        if !mask_bit {
-            return;
-        }
-    
-        let mut offset = 2;
-        
-        let actual_len: u64 = match len {
-            126 => {
-                let len = ((message[offset] as u64) << 8) | (message[offset + 1] as u64);
-                offset += 2;
-                len
-            }
-            127 => {
-                let len = ((message[offset] as u64) << 56) | ((message[offset + 1] as u64) << 48) |
-                ((message[offset + 2] as u64) << 40) | ((message[offset + 3] as u64) << 32) |
-                ((message[offset + 4] as u64) << 24) | ((message[offset + 5] as u64) << 16) |
-                ((message[offset + 6] as u64) << 8) | (message[offset + 7] as u64);
-                offset += 8;
-                len
+    pub fn ip(&self) -> Result<SocketAddr> {
+        Ok(self.stream.peer_addr()?)
+    }
+
+    /// A second handle to the same connection. Compression state belongs to
+    /// the original, so the copy sends and receives uncompressed frames.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self::from_stream(self.stream.try_clone()?, self.role, Vec::new()))
+    }
+}
+
+/// Locks the client even if a callback panicked while holding the lock.
+fn lock<I>(client: &RwLock<I>) -> RwLockWriteGuard<'_, I> {
+    client.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Value the server returns in `Sec-WebSocket-Accept` for `key`.
+fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key.as_bytes());
+    sha.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+    STANDARD.encode(sha.digest().bytes())
+}
+
+/// Bytes for handshake nonces and masking keys from the OS CSPRNG. Masking
+/// keys must not be predictable by the page that makes a client send data.
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|e| io::Error::other(format!("the OS random number generator failed: {e}")))?;
+    Ok(bytes)
+}
+
+/// Connects to the first address of `url` that answers within `CONNECT_TIMEOUT`.
+fn connect_tcp(url: &Url) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host did not resolve");
+    for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Masks `frame` with a random key when sent by a client.
+fn outgoing(frame: Frame, role: Role) -> io::Result<Frame> {
+    Ok(match role {
+        Role::Client => frame.masked(random_bytes()?),
+        Role::Server => frame,
+    })
+}
+
+/// How long [`WebSocket::connect`] waits for each address to accept, and
+/// then for the handshake response.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the peer's close frame after sending ours.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest message accepted from a peer, whether in one frame or fragmented.
+const MAX_MESSAGE_SIZE: u64 = 64 << 20;
+
+/// What a peer's frames amount to once reassembled.
+pub(crate) enum Event {
+    Message(Message),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(CloseCode, String),
+}
+
+/// Turns received bytes into events without doing any I/O, so the blocking
+/// `run` loop and the reactor share one implementation.
+pub(crate) struct Incoming {
+    role: Role,
+    received: Vec<u8>,
+    /// Opcode of a fragmented message that is still being received.
+    opcode: Option<OpCode>,
+    data: Vec<u8>,
+    /// Whether the current message's first frame had RSV1 set.
+    compressed: bool,
+    inflater: Option<Inflater>,
+}
+
+impl Incoming {
+    /// Takes over the bytes and decompression state the handshake left behind.
+    pub fn new<T: Transport>(ws: &mut WebSocket<T>) -> Self {
+        Self {
+            role: ws.role,
+            received: std::mem::take(&mut ws.pending),
+            opcode: None,
+            data: Vec::new(),
+            compressed: false,
+            inflater: ws.inflater.take(),
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.received.extend_from_slice(bytes);
+    }
+
+    /// Whether the undecoded bytes already hold more than the largest frame
+    /// allowed, so reading further can't help.
+    pub fn is_full(&self) -> bool {
+        // 14 bytes is the longest frame header.
+        self.received.len() as u64 > MAX_MESSAGE_SIZE + 14
+    }
+
+    /// The next complete event, `Ok(None)` if more bytes are needed, or the
+    /// close code to fail the connection with.
+    pub fn next_event(&mut self) -> Result<Option<Event>, CloseCode> {
+        loop {
+            let frame = match Frame::decode_limited(&self.received, MAX_MESSAGE_SIZE) {
+                Ok(Some((frame, used))) => {
+                    self.received.drain(..used);
+                    frame
+                }
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(e.close_code()),
+            };
+            if let Some(event) = self.process_frame(frame)? {
+                return Ok(Some(event));
             }
-            _ => len as u64
-        };
-    
-        let mask = [
-            message[offset],
-            message[offset + 1],
-            message[offset + 2],
-            message[offset + 3]
-        ];
-        offset += 4;
-    
-        let mut data = Vec::with_capacity(actual_len as usize);
-        data.extend_from_slice(&message[offset..]);
-    
-        for i in 0..data.len() {
-            data[i] ^= mask[i % 4];
-        }
-    
-        //println!("Processing message: fin={}, opcode={}, is_frag={}", fin, opt_code, is_frag);
-    
-        match opt_code {
-            0 => {
-                // Continuation Frame
-                if *is_frag {
-                    message_buffer.extend_from_slice(&data);
-                    if fin {
-                        let mut client = ws_interface.write().unwrap();
-                        client.on_message(message_buffer.clone());
-                        message_buffer.clear();
-                        *is_frag = false;
-                        *expected_type = None;
-                        //println!("Fragmented message completed");
-                    }
-                } else {
-                    println!("Received continuation frame without starting frame");
+        }
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> Result<Option<Event>, CloseCode> {
+        // Clients must mask, servers must not. RSV1 marks the first frame of a
+        // compressed message, the other reserved bits are never used.
+        let compressed = frame.rsv1 && self.inflater.is_some() && matches!(frame.opcode, OpCode::Text | OpCode::Binary);
+        if frame.mask.is_some() != (self.role == Role::Server) || (frame.rsv1 && !compressed) || frame.rsv2 || frame.rsv3 {
+            return Err(CloseCode::PROTOCOL_ERROR);
+        }
+
+        match frame.opcode {
+            OpCode::Continue => {
+                let Some(opcode) = self.opcode else {
+                    return Err(CloseCode::PROTOCOL_ERROR);
+                };
+                if (self.data.len() + frame.payload.len()) as u64 > MAX_MESSAGE_SIZE {
+                    return Err(CloseCode::MESSAGE_TOO_BIG);
+                }
+                self.data.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    return Ok(None);
                 }
+                self.opcode = None;
+                let data = std::mem::take(&mut self.data);
+                self.message(opcode, data).map(Some)
             }
-            1 | 2 => {
-                // Text or Binary Frame
-                if *is_frag {
-                    println!("Received new message while still processing fragments");
-                    return;
+            OpCode::Text | OpCode::Binary => {
+                if self.opcode.is_some() {
+                    return Err(CloseCode::PROTOCOL_ERROR);
                 }
-                
-                if fin {
-                    // Complete message in single frame
-                    let mut client = ws_interface.write().unwrap();
-                    client.on_message(data);
-                    //println!("Complete message received");
+                self.compressed = compressed;
+                if frame.fin {
+                    self.message(frame.opcode, frame.payload).map(Some)
                 } else {
-                    // Start of fragmented message
-                    message_buffer.clear();
-                    message_buffer.extend_from_slice(&data);
-                    *is_frag = true;
-                    *expected_type = Some(opt_code);
-                    //println!("Started fragmented message");
+                    self.opcode = Some(frame.opcode);
+                    self.data = frame.payload;
+                    Ok(None)
                 }
             }
-            8 => {
-                // Close Frame
-                let mut close_frame = Vec::with_capacity(data.len() + 2);
-                close_frame.push(0b10001000);
-                close_frame.push(data.len() as u8);
-                close_frame.extend_from_slice(&data);
-                let _ = stream.write_all(&close_frame);
-                let client = ws_interface.write().unwrap();
-                client.on_closed(ip);
-            }
-            9 => {
-                // Ping Frame
-                let mut pong = Vec::with_capacity(data.len() + 2);
-                pong.push(0b10001010);
-                pong.push(data.len() as u8);
-                pong.extend_from_slice(&data);
-                let _ = stream.write_all(&pong);
-            }
-            _ => println!("Unhandled opcode: {}", opt_code),
+            OpCode::Close => match frame.close_reason() {
+                Ok(Some((code, reason))) => Ok(Some(Event::Close(code, reason))),
+                Ok(None) => Ok(Some(Event::Close(CloseCode::NO_STATUS, String::new()))),
+                Err(_) => Err(CloseCode::PROTOCOL_ERROR),
+            },
+            OpCode::Ping => Ok(Some(Event::Ping(frame.payload))),
+            OpCode::Pong => Ok(Some(Event::Pong(frame.payload))),
         }
     }
 
-
-    pub fn ip(&self) -> SocketAddr {
-        self.stream.peer_addr().unwrap()
+    fn message(&mut self, opcode: OpCode, data: Vec<u8>) -> Result<Event, CloseCode> {
+        let data = match &mut self.inflater {
+            Some(inflater) if self.compressed => inflater.decompress(data, MAX_MESSAGE_SIZE).map_err(|e| match e {
+                InflateError::TooLarge => CloseCode::MESSAGE_TOO_BIG,
+                InflateError::Corrupt => CloseCode::INVALID_PAYLOAD,
+            })?,
+            _ => data,
+        };
+        Message::from_parts(opcode, data).map(Event::Message)
     }
 }
 
-impl Clone for WebSocket {
-    fn clone(&self) -> Self {
-        Self { stream: self.stream.try_clone().unwrap(), send_queue: VecDeque::with_capacity(10), close: false }
+/// Hands an event to the application, answering pings and close frames.
+/// Returns `false` once the connection is finished.
+pub(crate) fn deliver<T: Transport, I: WebSocketInterface<T> + ?Sized>(client: &mut I, event: Event, ip: SocketAddr) -> bool {
+    match event {
+        Event::Message(message) => client.on_message(message),
+        Event::Ping(data) => {
+            if let Err(e) = client.websocket_mut().send_frame(Frame::pong(data.clone())) {
+                net_log!(Error, "WebSocket {ip}: {e}");
+                client.on_closed(ip, CloseCode::INTERNAL_ERROR, "");
+                return false;
+            }
+            client.on_ping(&data);
+        }
+        Event::Pong(data) => {
+            client.websocket_mut().pong_received(&data);
+            client.on_pong(&data);
+        }
+        Event::Close(code, reason) => {
+            // Answer with the same code unless this already is the answer to ours.
+            let echo = if code == CloseCode::NO_STATUS { None } else { Some(code) };
+            let _ = client.websocket_mut().send_frame(Frame::close(echo, ""));
+            client.on_closed(ip, code, &reason);
+            return false;
+        }
     }
+    true
 }
 
-enum MessageLen {
-    Len(usize),
-    MissingBytes(usize),
-    ToShort,
+/// Sends a close frame with `code` after a protocol violation.
+pub(crate) fn fail<T: Transport, I: WebSocketInterface<T> + ?Sized>(client: &mut I, code: CloseCode, ip: SocketAddr) {
+    let _ = client.websocket_mut().send_frame(Frame::close(Some(code), ""));
+    client.on_closed(ip, code, "");
 }
 
 pub enum MessageDataType {
+    /// A lone continuation frame is invalid, so this is sent as binary.
     Continue,
     Text,
     Binary,
 }
 
-pub trait WebSocketInterface {
-    fn on_message(&mut self, data: Vec<u8>);
-    fn on_closed(&self, ip: SocketAddr);
-    fn websocket(&self) -> &WebSocket;
-    fn websocket_mut(&mut self) -> &mut WebSocket;
-}
\ No newline at end of file
+/// A complete data message, reassembled from its fragments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Message {
+    /// Text messages must be valid UTF-8, otherwise the connection is closed with 1007.
+    fn from_parts(opcode: OpCode, data: Vec<u8>) -> Result<Self, CloseCode> {
+        match opcode {
+            OpCode::Text => String::from_utf8(data).map(Self::Text).map_err(|_| CloseCode::INVALID_PAYLOAD),
+            _ => Ok(Self::Binary(data)),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Binary(data) => data,
+        }
+    }
+
+    /// A binary message holding `value`, e.g. for [`Hub::broadcast`](super::Hub::broadcast).
+    pub fn encode(value: &impl Encode) -> Self {
+        Self::Binary(value.to_bytes())
+    }
+
+    /// Decodes the whole payload, the counterpart of [`WebSocket::send_encoded`].
+    pub fn decode<D: Decode>(&self) -> Result<D, ReadError> {
+        D::from_bytes(self.as_bytes())
+    }
+}
+
+/// The application side of a connection. Implement it for the default
+/// [`Stream`] transport unless the socket comes from somewhere else.
+pub trait WebSocketInterface<T: Transport = Stream> {
+    fn on_message(&mut self, message: Message);
+    /// Called after the automatic pong has been sent.
+    fn on_ping(&mut self, _data: &[u8]) {}
+    fn on_pong(&mut self, _data: &[u8]) {}
+    /// `code` is [`CloseCode::NO_STATUS`] if the peer's close frame had no code
+    /// and [`CloseCode::ABNORMAL`] if the connection ended without one.
+    fn on_closed(&self, ip: SocketAddr, code: CloseCode, reason: &str);
+    fn websocket(&self) -> &WebSocket<T>;
+    fn websocket_mut(&mut self) -> &mut WebSocket<T>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Sender};
+    use crate::net::{MemoryStream, Router, Server};
+
+    struct Peer {
+        ws: WebSocket,
+        received: Option<Sender<Message>>,
+        closed: Option<Sender<(CloseCode, String)>>,
+    }
+
+    impl WebSocketInterface for Peer {
+        fn on_message(&mut self, message: Message) {
+            match &self.received {
+                Some(received) => {
+                    let _ = received.send(message);
+                    self.ws.close_with(CloseCode(4000), "done").unwrap();
+                }
+                None => self.ws.send_message(message).unwrap(),
+            }
+        }
+        fn on_closed(&self, _ip: SocketAddr, code: CloseCode, reason: &str) {
+            if let Some(closed) = &self.closed {
+                let _ = closed.send((code, reason.to_string()));
+            }
+        }
+        fn websocket(&self) -> &WebSocket {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket {
+            &mut self.ws
+        }
+    }
+
+    #[test]
+    fn client_masks_frames_and_reads_server_frames() {
+        let mut router = Router::new();
+        let (server_closed, closed) = channel();
+        let server_closed = std::sync::Mutex::new(server_closed);
+        router.websocket("/echo", move |ws, _, _| {
+            let closed = Some(server_closed.lock().unwrap().clone());
+            WebSocket::run(Arc::new(RwLock::new(Peer { ws, received: None, closed }))).unwrap()
+        });
+        let server = Server::bind("127.0.0.1:0", router).unwrap().spawn().unwrap();
+
+        assert!(matches!(WebSocket::connect("http://127.0.0.1/"), Err(Error::UnsupportedScheme(_))));
+        let mut ws = WebSocket::connect(&format!("ws://{}/echo", server.local_addr())).unwrap();
+        assert_eq!(ws.role(), Role::Client);
+        ws.send(b"hello", MessageDataType::Text).unwrap();
+        let (sender, received) = channel();
+        WebSocket::run(Arc::new(RwLock::new(Peer { ws, received: Some(sender), closed: None }))).unwrap();
+        assert_eq!(received.recv().unwrap(), Message::Text("hello".to_string()));
+        // The server saw our close code and reason and answered before `run` returned.
+        assert_eq!(closed.recv().unwrap(), (CloseCode(4000), "done".to_string()));
+
+        assert!(WebSocket::connect("wss://localhost/").is_err());
+        server.shutdown();
+    }
+
+    struct Beat {
+        ws: WebSocket,
+        events: Sender<Result<Duration, String>>,
+    }
+
+    impl WebSocketInterface for Beat {
+        fn on_message(&mut self, _message: Message) {}
+        fn on_pong(&mut self, _data: &[u8]) {
+            if let Some(latency) = self.ws.latency() {
+                let _ = self.events.send(Ok(latency));
+            }
+        }
+        fn on_closed(&self, _ip: SocketAddr, _code: CloseCode, reason: &str) {
+            let _ = self.events.send(Err(reason.to_string()));
+        }
+        fn websocket(&self) -> &WebSocket {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket {
+            &mut self.ws
+        }
+    }
+
+    #[test]
+    fn gives_up_on_servers_that_never_answer_the_handshake() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("ws://{}/", listener.local_addr().unwrap())).unwrap();
+        let stream = connect_tcp(&url).unwrap();
+        let _accepted = listener.accept().unwrap();
+
+        let started = Instant::now();
+        let result = WebSocket::handshake(stream.into(), &url, None, Duration::from_millis(200));
+        assert!(matches!(result, Err(Error::Handshake(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn heartbeat_measures_latency_and_drops_silent_peers() {
+        let (events, received) = channel();
+        let events = std::sync::Mutex::new(events);
+        let mut router = Router::new();
+        router.websocket("/", move |mut ws, _, _| {
+            ws.set_heartbeat(Duration::from_millis(20), Duration::from_millis(200));
+            let events = events.lock().unwrap().clone();
+            WebSocket::run(Arc::new(RwLock::new(Beat { ws, events }))).unwrap()
+        });
+        let server = Server::bind("127.0.0.1:0", router).unwrap().spawn().unwrap();
+        let url = format!("ws://{}/", server.local_addr());
+
+        // `run` answers pings on its own.
+        let ws = WebSocket::connect(&url).unwrap();
+        let (sender, _received) = channel();
+        let answering = Arc::new(RwLock::new(Peer { ws, received: Some(sender), closed: None }));
+        let client = answering.clone();
+        std::thread::spawn(move || WebSocket::run(client));
+        assert!(received.recv().unwrap().is_ok());
+        answering.write().unwrap().ws.disconnect();
+        while received.recv().unwrap().is_ok() {}
+
+        // A peer that never reads never answers.
+        let _silent = WebSocket::connect(&url).unwrap();
+        assert_eq!(received.recv_timeout(Duration::from_secs(5)).unwrap(), Err("heartbeat timed out".to_string()));
+        server.shutdown();
+    }
+
+    #[test]
+    fn negotiates_permessage_deflate() {
+        let mut router = Router::new();
+        router.websocket("/echo", |ws, _, _| {
+            assert!(ws.is_compressed());
+            WebSocket::run(Arc::new(RwLock::new(Peer { ws, received: None, closed: None }))).unwrap()
+        });
+        let server = Server::bind("127.0.0.1:0", router).unwrap().spawn().unwrap();
+
+        let config = DeflateConfig::default().max_window_bits(10);
+        let mut ws = WebSocket::connect_with(&format!("ws://{}/echo", server.local_addr()), Some(config)).unwrap();
+        assert!(ws.is_compressed());
+        let text = "{\"value\": 42} ".repeat(200);
+        ws.send_message(Message::Text(text.clone())).unwrap();
+        // The compressed frame is far smaller than the message.
+        assert!(ws.send_queue[0].len() < text.len() / 10);
+        let (sender, received) = channel();
+        WebSocket::run(Arc::new(RwLock::new(Peer { ws, received: Some(sender), closed: None }))).unwrap();
+        assert_eq!(received.recv().unwrap(), Message::Text(text));
+        server.shutdown();
+    }
+
+    struct Recorder {
+        ws: WebSocket<MemoryStream>,
+        events: Sender<std::result::Result<Message, CloseCode>>,
+    }
+
+    impl WebSocketInterface<MemoryStream> for Recorder {
+        fn on_message(&mut self, message: Message) {
+            self.ws.send_message(message.clone()).unwrap();
+            let _ = self.events.send(Ok(message));
+        }
+        fn on_closed(&self, ip: SocketAddr, code: CloseCode, _reason: &str) {
+            assert_eq!(ip, crate::net::unspecified_addr());
+            let _ = self.events.send(Err(code));
+        }
+        fn websocket(&self) -> &WebSocket<MemoryStream> {
+            &self.ws
+        }
+        fn websocket_mut(&mut self) -> &mut WebSocket<MemoryStream> {
+            &mut self.ws
+        }
+    }
+
+    /// Reads from `stream` until a whole frame arrived.
+    fn read_frame(stream: &mut MemoryStream) -> Frame {
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 256];
+        loop {
+            if let Some((frame, _)) = Frame::decode(&buffer).unwrap() {
+                return frame;
+            }
+            let n = stream.read(&mut chunk).unwrap();
+            assert!(n > 0, "stream closed");
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[test]
+    fn runs_over_an_in_memory_transport() {
+        let (mut peer, stream) = MemoryStream::pair();
+        let (events, received) = channel();
+        let ws = WebSocket::from_transport(stream, Role::Server);
+        let server = std::thread::spawn(move || WebSocket::run(Arc::new(RwLock::new(Recorder { ws, events }))));
+
+        // A fragmented, masked message comes back whole and unmasked.
+        peer.write_all(&Frame::text("hel").fin(false).masked([1, 2, 3, 4]).encode()).unwrap();
+        peer.write_all(&Frame::new(OpCode::Continue, "lo").masked([5, 6, 7, 8]).encode()).unwrap();
+        assert_eq!(received.recv().unwrap(), Ok(Message::Text("hello".into())));
+        let echo = read_frame(&mut peer);
+        assert_eq!((echo.opcode, echo.mask, &echo.payload[..]), (OpCode::Text, None, &b"hello"[..]));
+
+        // Clients must mask, so this fails the connection with 1002.
+        peer.write_all(&Frame::text("plain").encode()).unwrap();
+        assert_eq!(received.recv().unwrap(), Err(CloseCode::PROTOCOL_ERROR));
+        let close = read_frame(&mut peer);
+        assert_eq!(close.close_reason().unwrap().unwrap().0, CloseCode::PROTOCOL_ERROR);
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn sends_encoded_values() {
+        let (mut peer, stream) = MemoryStream::pair();
+        let mut ws = WebSocket::from_transport(stream, Role::Server);
+        ws.send_encoded(&(7u32, String::from("move"), vec![1.5f32, -2.0])).unwrap();
+        ws.flush().unwrap();
+
+        let frame = read_frame(&mut peer);
+        let message = Message::Binary(frame.payload);
+        assert_eq!(message, Message::encode(&(7u32, "move", vec![1.5f32, -2.0])));
+        assert_eq!(message.decode(), Ok((7u32, String::from("move"), vec![1.5f32, -2.0])));
+        assert!(message.decode::<(u32, String)>().is_err());
+        // A peer claiming a huge string gets an error, not a panic.
+        let hostile = Message::Binary(vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+        assert!(matches!(hostile.decode::<String>(), Err(ReadError::TooLong { .. })));
+    }
+}
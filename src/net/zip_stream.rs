@@ -0,0 +1,465 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{write::DeflateEncoder, Compression, Crc};
+
+use super::date::civil_from_days;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIR: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIR: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+
+/// General purpose flags: sizes follow in a data descriptor, names are UTF-8.
+const FLAG_DESCRIPTOR: u16 = 0x0008;
+const FLAG_UTF8: u16 = 0x0800;
+
+/// Files at least this large get Zip64 records, leaving headroom for deflate
+/// output that is slightly larger than its input.
+const ZIP64_THRESHOLD: u64 = 0xF000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZipMethod {
+    Stored,
+    Deflated,
+}
+
+impl ZipMethod {
+    const fn id(self) -> u16 {
+        match self {
+            Self::Stored => 0,
+            Self::Deflated => 8,
+        }
+    }
+}
+
+/// Controls which files end up in a streamed archive and how they are packed.
+#[derive(Debug, Clone)]
+pub struct ZipOptions {
+    exclude: Vec<String>,
+    stored: Vec<String>,
+    level: u32,
+}
+
+impl Default for ZipOptions {
+    /// Excludes nothing and stores formats that are already compressed.
+    fn default() -> Self {
+        let stored = [
+            "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "jar", "apk", "png", "apng", "jpg", "jpeg", "gif",
+            "webp", "avif", "mp3", "mp4", "m4a", "m4v", "ogg", "opus", "webm", "mkv", "mov", "flac", "aac", "woff",
+            "woff2",
+        ];
+        Self { exclude: Vec::new(), stored: stored.iter().map(|s| s.to_string()).collect(), level: 6 }
+    }
+}
+
+impl ZipOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skips files and directories matching `pattern`. Patterns without a `/`
+    /// are matched against the file name, others against the path relative to
+    /// the archive root. `*` and `?` stay within one path segment, `**` spans
+    /// several.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    /// Uses `method` for files with `extension`.
+    pub fn method_for(mut self, extension: &str, method: ZipMethod) -> Self {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.stored.retain(|e| *e != extension);
+        if method == ZipMethod::Stored {
+            self.stored.push(extension);
+        }
+        self
+    }
+
+    /// Deflate level from 0 to 9.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+        self
+    }
+
+    pub fn is_excluded(&self, relative_path: &str) -> bool {
+        let name = relative_path.rsplit('/').next().unwrap_or(relative_path);
+        self.exclude.iter().any(|pattern| match pattern.contains('/') {
+            true => glob_match(pattern.trim_start_matches('/').as_bytes(), relative_path.as_bytes()),
+            false => glob_match(pattern.as_bytes(), name.as_bytes()),
+        })
+    }
+
+    pub fn method(&self, path: &Path) -> ZipMethod {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if self.stored.iter().any(|s| s.eq_ignore_ascii_case(ext)) => ZipMethod::Stored,
+            _ => ZipMethod::Deflated,
+        }
+    }
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let segment = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+            (0..=segment).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob_match(rest, tail)),
+        [p, rest @ ..] => matches!(text, [c, tail @ ..] if c == p && glob_match(rest, tail)),
+    }
+}
+
+struct Entry {
+    name: String,
+    method: ZipMethod,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u64,
+    uncompressed: u64,
+    offset: u64,
+    directory: bool,
+    zip64: bool,
+}
+
+/// Counts what passes through so entry offsets are known without seeking.
+struct CountingWriter<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes a ZIP archive front to back without seeking, so it can go straight
+/// to a socket. Sizes and checksums follow each entry in a data descriptor.
+pub struct ZipStreamWriter<W: Write> {
+    out: CountingWriter<W>,
+    entries: Vec<Entry>,
+}
+
+impl<W: Write> ZipStreamWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { out: CountingWriter { inner, written: 0 }, entries: Vec::new() }
+    }
+
+    /// Adds every file below `dir` that `options` does not exclude.
+    /// Symbolic links are skipped, so a link to a parent can't recurse forever
+    /// and a link out of `dir` can't leak files outside it.
+    pub fn add_directory_contents(&mut self, dir: &Path, options: &ZipOptions) -> io::Result<()> {
+        self.add_dir_recursive(dir, "", options)
+    }
+
+    fn add_dir_recursive(&mut self, dir: &Path, prefix: &str, options: &ZipOptions) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let relative = format!("{prefix}{name}");
+            if options.is_excluded(&relative) {
+                continue;
+            }
+            let path = entry.path();
+            let metadata = fs::symlink_metadata(&path)?;
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+
+            if metadata.is_dir() {
+                self.add_directory(&relative, modified)?;
+                self.add_dir_recursive(&path, &format!("{relative}/"), options)?;
+            } else if metadata.is_file() {
+                let mut file = File::open(&path)?;
+                self.add_file(&relative, &mut file, metadata.len(), modified, options.method(&path), options.level)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_directory(&mut self, name: &str, modified: SystemTime) -> io::Result<()> {
+        let name = format!("{}/", name.trim_end_matches('/'));
+        let (time, date) = dos_time(modified);
+        let offset = self.out.written;
+
+        self.write_local_header(&name, ZipMethod::Stored, FLAG_UTF8, time, date, false)?;
+        self.entries.push(Entry {
+            name,
+            method: ZipMethod::Stored,
+            time,
+            date,
+            crc: 0,
+            compressed: 0,
+            uncompressed: 0,
+            offset,
+            directory: true,
+            zip64: false,
+        });
+        Ok(())
+    }
+
+    /// Streams `reader` into a new entry. `size_hint` only decides whether
+    /// Zip64 records are needed.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        reader: &mut impl Read,
+        size_hint: u64,
+        modified: SystemTime,
+        method: ZipMethod,
+        level: u32,
+    ) -> io::Result<()> {
+        let (time, date) = dos_time(modified);
+        let offset = self.out.written;
+        let zip64 = size_hint >= ZIP64_THRESHOLD;
+        self.write_local_header(name, method, FLAG_UTF8 | FLAG_DESCRIPTOR, time, date, zip64)?;
+
+        let mut crc = Crc::new();
+        let mut uncompressed = 0u64;
+        let start = self.out.written;
+        let mut buffer = vec![0; 64 * 1024];
+
+        match method {
+            ZipMethod::Stored => loop {
+                let n = read_some(reader, &mut buffer)?;
+                if n == 0 {
+                    break;
+                }
+                crc.update(&buffer[..n]);
+                uncompressed += n as u64;
+                self.out.write_all(&buffer[..n])?;
+            },
+            ZipMethod::Deflated => {
+                let mut encoder = DeflateEncoder::new(&mut self.out, Compression::new(level));
+                loop {
+                    let n = read_some(reader, &mut buffer)?;
+                    if n == 0 {
+                        break;
+                    }
+                    crc.update(&buffer[..n]);
+                    uncompressed += n as u64;
+                    encoder.write_all(&buffer[..n])?;
+                }
+                encoder.finish()?;
+            }
+        }
+
+        let compressed = self.out.written - start;
+        if !zip64 && (compressed > u32::MAX as u64 || uncompressed > u32::MAX as u64) {
+            return Err(io::Error::other("file grew past the Zip64 threshold while streaming"));
+        }
+
+        self.out.write_all(&DATA_DESCRIPTOR.to_le_bytes())?;
+        self.out.write_all(&crc.sum().to_le_bytes())?;
+        if zip64 {
+            self.out.write_all(&compressed.to_le_bytes())?;
+            self.out.write_all(&uncompressed.to_le_bytes())?;
+        } else {
+            self.out.write_all(&(compressed as u32).to_le_bytes())?;
+            self.out.write_all(&(uncompressed as u32).to_le_bytes())?;
+        }
+
+        self.entries.push(Entry {
+            name: name.to_string(),
+            method,
+            time,
+            date,
+            crc: crc.sum(),
+            compressed,
+            uncompressed,
+            offset,
+            directory: false,
+            zip64,
+        });
+        Ok(())
+    }
+
+    fn write_local_header(&mut self, name: &str, method: ZipMethod, flags: u16, time: u16, date: u16, zip64: bool) -> io::Result<()> {
+        let out = &mut self.out;
+        out.write_all(&LOCAL_HEADER.to_le_bytes())?;
+        out.write_all(&(if zip64 { 45u16 } else { 20u16 }).to_le_bytes())?;
+        out.write_all(&flags.to_le_bytes())?;
+        out.write_all(&method.id().to_le_bytes())?;
+        out.write_all(&time.to_le_bytes())?;
+        out.write_all(&date.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        let size: u32 = if zip64 { u32::MAX } else { 0 };
+        out.write_all(&size.to_le_bytes())?;
+        out.write_all(&size.to_le_bytes())?;
+        out.write_all(&(name.len() as u16).to_le_bytes())?;
+        out.write_all(&(if zip64 { 20u16 } else { 0u16 }).to_le_bytes())?;
+        out.write_all(name.as_bytes())?;
+        if zip64 {
+            out.write_all(&1u16.to_le_bytes())?;
+            out.write_all(&16u16.to_le_bytes())?;
+            out.write_all(&[0; 16])?;
+        }
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let cd_start = self.out.written;
+
+        for entry in &self.entries {
+            let zip64 = entry.zip64 || entry.offset >= u32::MAX as u64;
+            let out = &mut self.out;
+            out.write_all(&CENTRAL_HEADER.to_le_bytes())?;
+            // Made by Unix, spec version 4.5.
+            out.write_all(&(0x0300u16 | 45).to_le_bytes())?;
+            out.write_all(&(if zip64 { 45u16 } else { 20u16 }).to_le_bytes())?;
+            let flags = if entry.directory { FLAG_UTF8 } else { FLAG_UTF8 | FLAG_DESCRIPTOR };
+            out.write_all(&flags.to_le_bytes())?;
+            out.write_all(&entry.method.id().to_le_bytes())?;
+            out.write_all(&entry.time.to_le_bytes())?;
+            out.write_all(&entry.date.to_le_bytes())?;
+            out.write_all(&entry.crc.to_le_bytes())?;
+            if zip64 {
+                out.write_all(&u32::MAX.to_le_bytes())?;
+                out.write_all(&u32::MAX.to_le_bytes())?;
+            } else {
+                out.write_all(&(entry.compressed as u32).to_le_bytes())?;
+                out.write_all(&(entry.uncompressed as u32).to_le_bytes())?;
+            }
+            out.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+            out.write_all(&(if zip64 { 28u16 } else { 0u16 }).to_le_bytes())?;
+            // Comment length, disk number, internal attributes.
+            out.write_all(&[0; 6])?;
+            let external: u32 = if entry.directory { (0o40755 << 16) | 0x10 } else { 0o100644 << 16 };
+            out.write_all(&external.to_le_bytes())?;
+            let offset = if zip64 { u32::MAX } else { entry.offset as u32 };
+            out.write_all(&offset.to_le_bytes())?;
+            out.write_all(entry.name.as_bytes())?;
+            if zip64 {
+                out.write_all(&1u16.to_le_bytes())?;
+                out.write_all(&24u16.to_le_bytes())?;
+                out.write_all(&entry.uncompressed.to_le_bytes())?;
+                out.write_all(&entry.compressed.to_le_bytes())?;
+                out.write_all(&entry.offset.to_le_bytes())?;
+            }
+        }
+
+        let cd_end = self.out.written;
+        let cd_size = cd_end - cd_start;
+        let count = self.entries.len() as u64;
+        let zip64 = count >= u16::MAX as u64 || cd_start >= u32::MAX as u64 || cd_size >= u32::MAX as u64;
+        let out = &mut self.out;
+
+        if zip64 {
+            out.write_all(&ZIP64_END_OF_CENTRAL_DIR.to_le_bytes())?;
+            out.write_all(&44u64.to_le_bytes())?;
+            out.write_all(&(0x0300u16 | 45).to_le_bytes())?;
+            out.write_all(&45u16.to_le_bytes())?;
+            out.write_all(&[0; 8])?;
+            out.write_all(&count.to_le_bytes())?;
+            out.write_all(&count.to_le_bytes())?;
+            out.write_all(&cd_size.to_le_bytes())?;
+            out.write_all(&cd_start.to_le_bytes())?;
+
+            out.write_all(&ZIP64_LOCATOR.to_le_bytes())?;
+            out.write_all(&0u32.to_le_bytes())?;
+            out.write_all(&cd_end.to_le_bytes())?;
+            out.write_all(&1u32.to_le_bytes())?;
+        }
+
+        out.write_all(&END_OF_CENTRAL_DIR.to_le_bytes())?;
+        out.write_all(&[0; 4])?;
+        let short_count = count.min(u16::MAX as u64) as u16;
+        out.write_all(&short_count.to_le_bytes())?;
+        out.write_all(&short_count.to_le_bytes())?;
+        out.write_all(&(cd_size.min(u32::MAX as u64) as u32).to_le_bytes())?;
+        out.write_all(&(cd_start.min(u32::MAX as u64) as u32).to_le_bytes())?;
+        out.write_all(&0u16.to_le_bytes())?;
+        out.flush()?;
+
+        Ok(self.out.inner)
+    }
+}
+
+fn read_some(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// MS-DOS time and date fields in UTC. DOS dates cannot go before 1980.
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let rem = secs % 86400;
+    let time = ((rem / 3600) << 11) | ((rem / 60 % 60) << 5) | (rem % 60 / 2);
+    let date = (((year - 1980).min(127) as u32) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match(b"*.tmp", b"a.tmp"));
+        assert!(!glob_match(b"*.tmp", b"dir/a.tmp"));
+        assert!(glob_match(b"**/*.tmp", b"dir/sub/a.tmp"));
+        assert!(glob_match(b"cache/**", b"cache/x/y"));
+        assert!(glob_match(b"?.txt", b"a.txt"));
+    }
+
+    #[test]
+    fn streamed_archive_is_readable() {
+        let dir = std::env::temp_dir().join(format!("iron_oxide_zip_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub/.git")).unwrap();
+        fs::write(dir.join("a.txt"), "hello hello hello hello").unwrap();
+        fs::write(dir.join("sub/b.png"), [1, 2, 3]).unwrap();
+        fs::write(dir.join("sub/skip.tmp"), "x").unwrap();
+        fs::write(dir.join("sub/.git/config"), "x").unwrap();
+        #[cfg(unix)]
+        {
+            let _ = std::os::unix::fs::symlink(&dir, dir.join("sub/loop"));
+            let _ = std::os::unix::fs::symlink("/etc/hostname", dir.join("outside.txt"));
+        }
+
+        let options = ZipOptions::new().exclude("*.tmp").exclude(".git");
+        let mut writer = ZipStreamWriter::new(Vec::new());
+        writer.add_directory_contents(&dir, &options).unwrap();
+        let bytes = writer.finish().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(names.len(), 3);
+        assert!(!names.iter().any(|n| n.contains("tmp") || n.contains(".git")));
+
+        let mut text = String::new();
+        archive.by_name("a.txt").unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello hello hello hello");
+        let png = archive.by_name("sub/b.png").unwrap();
+        assert_eq!(png.compression(), zip::CompressionMethod::Stored);
+    }
+}